@@ -0,0 +1,2 @@
+drop table PdfInfo;
+drop table Outline;
\ No newline at end of file
@@ -0,0 +1,16 @@
+create table if not exists PdfInfo (
+    document char(36) primary key,
+    author varchar(256),
+    subject varchar(256),
+    -- JSON array of the keywords, NULL if there are none
+    keywords text
+);
+
+create table if not exists Outline (
+    document char(36) not null,
+    position integer not null,
+    depth integer not null,
+    title varchar(256) not null,
+    page integer,
+    primary key (document, position)
+);
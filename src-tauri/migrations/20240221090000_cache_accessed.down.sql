@@ -0,0 +1 @@
+alter table Cache drop column accessed;
\ No newline at end of file
@@ -0,0 +1 @@
+alter table Cache add column accessed datetime not null default '1970-01-01 00:00:00';
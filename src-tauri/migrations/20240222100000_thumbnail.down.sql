@@ -0,0 +1 @@
+alter table Cache drop column thumbnail;
\ No newline at end of file
@@ -0,0 +1 @@
+alter table Cache add column thumbnail varchar(16);
@@ -0,0 +1,2 @@
+drop table Replica;
+drop table SyncLog;
\ No newline at end of file
@@ -0,0 +1,12 @@
+-- identifies this data directory to sync peers, has at most one row
+create table if not exists Replica (
+    id char(16) primary key
+);
+
+-- the version of every document on this side after the last sync with a peer
+create table if not exists SyncLog (
+    peer char(16) not null,
+    document char(36) not null,
+    version varchar(16) not null,
+    primary key (peer, document)
+);
@@ -0,0 +1 @@
+drop table Journal;
\ No newline at end of file
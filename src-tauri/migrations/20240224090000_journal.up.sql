@@ -0,0 +1,9 @@
+-- append-only, every mutation of a document is recorded in the transaction that makes it
+create table if not exists Journal (
+    seq integer primary key autoincrement,
+    time datetime not null,
+    document char(36) not null,
+    operation text not null
+);
+
+create index if not exists JournalDocument on Journal (document);
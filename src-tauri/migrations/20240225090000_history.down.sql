@@ -0,0 +1 @@
+drop table History;
\ No newline at end of file
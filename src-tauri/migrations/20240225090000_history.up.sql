@@ -0,0 +1,7 @@
+-- undo and redo stacks, operations are stored like in the Journal
+create table if not exists History (
+    seq integer primary key autoincrement,
+    stack varchar(4) not null,
+    document char(36) not null,
+    operation text not null
+);
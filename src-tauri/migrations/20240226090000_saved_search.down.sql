@@ -0,0 +1 @@
+drop table SavedSearch;
\ No newline at end of file
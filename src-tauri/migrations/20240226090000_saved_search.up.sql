@@ -0,0 +1,9 @@
+-- filters and sort are stored as JSON
+create table if not exists SavedSearch (
+    name varchar(64) primary key not null,
+    pos_filter text not null,
+    neg_filter text not null,
+    crib varchar(256) not null,
+    sort text not null,
+    result_limit integer
+);
@@ -0,0 +1,2 @@
+drop table Collection;
+drop table CollectionDocument;
\ No newline at end of file
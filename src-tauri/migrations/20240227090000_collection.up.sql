@@ -0,0 +1,11 @@
+-- manually ordered lists of documents, a document can be in several collections
+create table if not exists Collection (
+    name varchar(64) primary key not null
+);
+
+create table if not exists CollectionDocument (
+    collection varchar(64) not null,
+    document char(36) not null,
+    position integer not null,
+    primary key (collection, document)
+);
@@ -0,0 +1 @@
+drop table TagAlias;
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- alternative names of tags, they are replaced by the tag they point to wherever tags are used
+create table if not exists TagAlias (
+    alias varchar(32) primary key not null,
+    tag varchar(32) not null
+);
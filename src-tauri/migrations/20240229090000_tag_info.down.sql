@@ -0,0 +1,3 @@
+drop trigger TagInfoInsert;
+drop index TagTag;
+drop table TagInfo;
\ No newline at end of file
@@ -0,0 +1,17 @@
+-- properties of tags, a tag stays here when no document has it anymore
+create table if not exists TagInfo (
+    name varchar(32) primary key not null,
+    color varchar(16),
+    icon varchar(64),
+    description text,
+    pinned boolean not null default false
+);
+
+insert or ignore into TagInfo (name) select distinct tag from Tag;
+
+create trigger if not exists TagInfoInsert after insert on Tag
+begin
+    insert or ignore into TagInfo (name) values (new.tag);
+end;
+
+create index if not exists TagTag on Tag (tag);
@@ -0,0 +1 @@
+alter table History drop column rows;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- rows of other tables that belonged to a deleted document, put back when the deletion is undone
+alter table History add column rows text;
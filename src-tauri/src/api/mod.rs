@@ -0,0 +1,327 @@
+use std::net::{Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use eyre::{eyre, Result};
+#[cfg(unix)]
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use log::info;
+#[cfg(unix)]
+use log::warn;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::document;
+use crate::types::*;
+use crate::FilespiderState;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone)]
+struct ApiState {
+    state: FilespiderState,
+    token: String,
+}
+
+pub enum Listen {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Listen {
+    pub fn localhost(port: u16) -> Self {
+        Self::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+    }
+}
+
+/// serves the document store as JSON over HTTP until an error occurs
+pub async fn serve(state: FilespiderState, listen: Listen, token: String) -> Result<()> {
+    if token.is_empty() {
+        return Err(eyre!("refusing to start the API server without a token"));
+    }
+
+    let app = router(ApiState { state, token });
+
+    match listen {
+        Listen::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            info!("API server listening on {}", listener.local_addr()?);
+
+            axum::serve(listener, app).await?;
+        }
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            crate::directories::remove_stale_socket(&path).await?;
+            let listener = tokio::net::UnixListener::bind(&path)?;
+            info!("API server listening on {}", path.display());
+
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("failed to accept an API connection: {:?}", e);
+                        continue;
+                    }
+                };
+                let service = TowerToHyperService::new(app.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        warn!("API connection failed: {:?}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn router(api: ApiState) -> Router {
+    Router::new()
+        .route("/documents", get(search).post(create))
+        .route(
+            "/documents/:id",
+            get(get_meta).patch(patch_meta).delete(delete),
+        )
+        .route("/documents/:id/render", get(render))
+        .route("/documents/:id/file", put(upload))
+        .route("/tags", get(get_tags))
+        .layer(middleware::from_fn_with_state(api.clone(), auth))
+        .with_state(api)
+}
+
+async fn auth(State(api): State<ApiState>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|t| tokens_match(t, &api.token));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+/// compares every byte, so the token can't be guessed byte by byte from the response times
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+struct ApiError(StatusCode, eyre::Report);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, format!("{:?}", self.1)).into_response()
+    }
+}
+
+impl<E: Into<eyre::Report>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, e.into())
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+/// 404 instead of the error the operation would fail with
+async fn find(id: &Uuid) -> ApiResult<()> {
+    document::document_exists(id)
+        .await
+        .map_err(|e| ApiError(StatusCode::NOT_FOUND, e))
+}
+
+/// tag lists are comma separated
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    pos_filter: String,
+    #[serde(default)]
+    neg_filter: String,
+    #[serde(default)]
+    crib: String,
+    #[serde(default)]
+    page: u32,
+    #[serde(default = "default_page_length")]
+    page_length: u32,
+    #[serde(default = "default_sort")]
+    sort: SearchSortCriterium,
+    #[serde(default)]
+    ascending: bool,
+}
+
+fn default_page_length() -> u32 {
+    50
+}
+
+fn default_sort() -> SearchSortCriterium {
+    SearchSortCriterium::CreationTime
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+async fn search(
+    State(api): State<ApiState>,
+    Query(q): Query<SearchQuery>,
+) -> ApiResult<Json<SearchResponse>> {
+    if let SearchSortCriterium::MetadataField(field) = &q.sort {
+        if !["author", "subject", "keywords"].contains(&field.as_str()) {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                eyre!("unknown metadata field {}", field),
+            ));
+        }
+    }
+
+    Ok(Json(
+        document::search(
+            &api.state.pool,
+            split_tags(&q.pos_filter),
+            split_tags(&q.neg_filter),
+            q.crib,
+            q.page,
+            q.page_length,
+            vec![(q.sort, q.ascending)],
+        )
+        .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+struct CreateRequest {
+    title: String,
+    doc_type: Option<DocType>,
+    #[serde(default)]
+    tags: Vec<String>,
+    extension: Option<String>,
+    file: document::File,
+}
+
+async fn create(
+    State(api): State<ApiState>,
+    Json(req): Json<CreateRequest>,
+) -> ApiResult<Json<Uuid>> {
+    // clients must not be able to make FileSpider copy arbitrary local files
+    if let document::File::Path(_) = req.file {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            eyre!("files have to be uploaded as Blob"),
+        ));
+    }
+
+    Ok(Json(
+        document::create(
+            &api.state.pool,
+            req.title,
+            req.doc_type,
+            req.tags,
+            req.extension,
+            req.file,
+        )
+        .await?,
+    ))
+}
+
+async fn get_meta(State(api): State<ApiState>, Path(id): Path<Uuid>) -> ApiResult<Json<Meta>> {
+    find(&id).await?;
+    Ok(Json(document::get_meta(&api.state.pool, id).await?))
+}
+
+async fn patch_meta(
+    State(api): State<ApiState>,
+    Path(id): Path<Uuid>,
+    Json(patch): Json<MetaPatch>,
+) -> ApiResult<StatusCode> {
+    find(&id).await?;
+
+    // tags are compared in their canonical form like `document::patch_meta` does
+    let tags = document::get_meta(&api.state.pool, id).await?.tags;
+    let patch =
+        document::tags::canonical_patch(&mut *api.state.pool.acquire().await?, patch).await?;
+    let invalid = match &patch {
+        MetaPatch::AddTag(tag) if tag.is_empty() => Some("tags can't be empty"),
+        MetaPatch::AddTag(tag) if tags.contains(tag) => Some("document already has the tag"),
+        MetaPatch::RemoveTag(tag) if !tags.contains(tag) => Some("document doesn't have the tag"),
+        _ => None,
+    };
+    if let Some(invalid) = invalid {
+        return Err(ApiError(StatusCode::BAD_REQUEST, eyre!(invalid)));
+    }
+
+    document::patch_meta(&api.state.pool, id, patch).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete(State(api): State<ApiState>, Path(id): Path<Uuid>) -> ApiResult<StatusCode> {
+    find(&id).await?;
+    document::delete(&api.state.pool, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn render(State(api): State<ApiState>, Path(id): Path<Uuid>) -> ApiResult<Response> {
+    find(&id).await?;
+    let cache_size_limit = api.state.settings.lock().await.cache_size_limit;
+    let (path, render_type) =
+        document::render::render(&api.state.pool, &api.state.renderers, id, cache_size_limit)
+            .await?;
+
+    let content_type = match render_type {
+        RenderType::Plain => "text/plain; charset=utf-8",
+        RenderType::Html => "text/html; charset=utf-8",
+        RenderType::Pdf => "application/pdf",
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, content_type)],
+        tokio::fs::read(path).await?,
+    )
+        .into_response())
+}
+
+/// replaces the contents of the main file of a document with the request body
+async fn upload(
+    State(api): State<ApiState>,
+    Path(id): Path<Uuid>,
+    body: Bytes,
+) -> ApiResult<StatusCode> {
+    find(&id).await?;
+    document::replace_file(&api.state.pool, id, document::File::Blob(body.to_vec())).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct TagQuery {
+    #[serde(default)]
+    crib: String,
+}
+
+async fn get_tags(
+    State(api): State<ApiState>,
+    Query(q): Query<TagQuery>,
+) -> ApiResult<Json<Vec<String>>> {
+    Ok(Json(document::get_tags(&api.state.pool, q.crib).await?))
+}
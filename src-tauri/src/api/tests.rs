@@ -0,0 +1,95 @@
+use tempfile::tempdir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::test;
+
+use crate::api::*;
+use crate::settings::Settings;
+
+/// sends a request without a body and returns the status code
+async fn status(addr: SocketAddr, method: &str, path: &str, token: Option<&str>) -> Result<u16> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let authorization = token
+        .map(|t| format!("Authorization: Bearer {}\r\n", t))
+        .unwrap_or_default();
+    stream
+        .write_all(
+            format!(
+                "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+                method, path, authorization
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response
+        .split(' ')
+        .nth(1)
+        .ok_or(eyre!("invalid response {}", response))?
+        .parse()?)
+}
+
+#[test]
+async fn routing() {
+    // documents are looked up in the global storage
+    let (_guard, _) = crate::test_data_directory().await;
+    let dtmp = tempdir().unwrap();
+
+    if let Err(e) = async {
+        let pool = crate::db::open(&format!("{}/filespider.sqlite", dtmp.path().display())).await?;
+        let state = FilespiderState::new(
+            pool,
+            Settings::default().await?,
+            None,
+            #[cfg(target_os = "linux")]
+            None,
+        );
+        let token = "secret";
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let app = router(ApiState {
+            state,
+            token: token.to_string(),
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        assert_eq!(status(addr, "GET", "/tags", None).await?, 401);
+        assert_eq!(status(addr, "GET", "/tags", Some("secreT")).await?, 401);
+        assert_eq!(status(addr, "GET", "/tags", Some("secret2")).await?, 401);
+        assert_eq!(status(addr, "GET", "/tags", Some(token)).await?, 200);
+
+        let missing = Uuid::new_v4();
+        for (method, path, expected) in [
+            ("GET", "/documents", 200),
+            ("GET", "/documents?sort=CreationTime&ascending=true", 200),
+            ("GET", "/documents?page=first", 400),
+            ("GET", "/documents/not-an-id", 400),
+            ("GET", &format!("/documents/{}", missing), 404),
+            ("DELETE", &format!("/documents/{}", missing), 404),
+            ("GET", &format!("/documents/{}/render", missing), 404),
+            ("GET", "/collections", 404),
+            ("POST", "/tags", 405),
+        ] {
+            assert_eq!(
+                status(addr, method, path, Some(token)).await?,
+                expected,
+                "{} {} wasn't routed correctly",
+                method,
+                path
+            );
+        }
+
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secrets"));
+        assert!(!tokens_match("", "secret"));
+
+        Ok::<(), eyre::Report>(())
+    }
+    .await
+    {
+        panic!("Error: {}", e);
+    }
+}
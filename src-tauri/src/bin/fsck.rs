@@ -0,0 +1,75 @@
+use clap::Parser;
+use eyre::Result;
+
+use filespider::settings::Settings;
+use filespider::{db, directories, document};
+
+/// Checks the FileSpider DB against the document directories.
+/// Don't run this with --repair while FileSpider is running.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// adopt orphaned directories, prune dangling rows and clear the cache
+    #[arg(long)]
+    repair: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Info)
+        .init();
+
+    directories::create_directories().await?;
+
+    let pool = db::init().await?;
+    document::storage::init(&Settings::load().await?.0.storage)?;
+
+    let report = document::fsck::fsck(&pool, args.repair).await?;
+
+    for id in report.orphaned_directories.iter() {
+        println!("orphaned directory: {}", id);
+    }
+    for id in report.missing_directories.iter() {
+        println!("missing directory: {}", id);
+    }
+    for (id, tag) in report.dangling_tags.iter() {
+        println!("dangling tag: {} on {}", tag, id);
+    }
+    for id in report.dangling_cache_entries.iter() {
+        println!("dangling cache entry: {}", id);
+    }
+    for id in report.dangling_pdf_info.iter() {
+        println!("dangling pdf info: {}", id);
+    }
+    for (collection, id) in report.dangling_collection_entries.iter() {
+        println!("dangling collection entry: {} in {}", id, collection);
+    }
+    for seq in report.invalid_journal_entries.iter() {
+        println!("invalid journal entry: {}", seq);
+    }
+    for seq in report.stale_history_entries.iter() {
+        println!("stale history entry: {}", seq);
+    }
+    for tag in report.inconsistent_tag_info.iter() {
+        println!("inconsistent tag info: {}", tag);
+    }
+    for id in report.invalid_sync_log_entries.iter() {
+        println!("invalid sync log entry: {}", id);
+    }
+    for name in report.stale_staging_directories.iter() {
+        println!("stale staging directory: {}", name);
+    }
+
+    if report.is_clean() {
+        println!("no inconsistencies found");
+    } else if report.repaired {
+        println!("repaired");
+    } else {
+        println!("run with --repair to fix");
+    }
+
+    Ok(())
+}
@@ -1,13 +1,13 @@
 use std::str::FromStr;
 
 use clap::Parser;
-use eyre::Result;
+use eyre::{Result, WrapErr};
 use sqlx::mysql::{MySqlConnectOptions, MySqlRow};
 use sqlx::{MySqlPool, Row};
-use tokio::process::Command;
 use uuid::Uuid;
 
-use filespider::{db, directories, document::File};
+use filespider::settings::Settings;
+use filespider::{db, directories, document::storage, document::File};
 
 #[derive(Parser, Debug)]
 #[command()]
@@ -27,6 +27,7 @@ async fn main() -> Result<()> {
 
     let pool_new = db::init().await?;
     sqlx::migrate!().run(&pool_new).await?;
+    storage::init(&Settings::load().await?.0.storage)?;
 
     let pool_old = MySqlPool::connect_with(MySqlConnectOptions::from_str(&args.mysql_url)?).await?;
 
@@ -56,28 +57,31 @@ async fn main() -> Result<()> {
         )
         .await?;
 
-        let docdir = filespider::document::get_document_directory(&id)?;
         let old_id = Uuid::try_parse(&r.get::<String, &str>("id"))?;
         let ext = match r.get::<Option<String>, &str>("fileExtension") {
             Some(s) => format!(".{}", s),
             None => String::new(),
         };
-        if !Command::new("sh")
-            .arg("-c")
-            .arg(format!(
-                "mkdir {}/{}; cp -r {}/{}/* {} && mv {}/{}{} {}/{}{}",
-                docdir, id, args.document_directory, old_id, docdir, docdir, old_id, ext, docdir, id, ext,
-            ))
-            .spawn()?
-            .wait()
-            .await?
-            .success()
-        {
-            return Err(eyre::eyre!(
-                "Failed to copy files of document {} to {}",
-                old_id,
-                id
-            ));
+
+        let mut entries =
+            tokio::fs::read_dir(format!("{}/{}", args.document_directory, old_id)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let name = if name == format!("{}{}", old_id, ext) {
+                format!("{}{}", id, ext)
+            } else {
+                name
+            };
+            storage::get()?
+                .write(&id, &name, &tokio::fs::read(entry.path()).await?)
+                .await
+                .wrap_err(format!(
+                    "Failed to copy files of document {} to {}",
+                    old_id, id
+                ))?;
         }
     }
 
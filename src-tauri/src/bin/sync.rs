@@ -0,0 +1,115 @@
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use clap::Parser;
+use eyre::{eyre, Result};
+#[cfg(unix)]
+use log::{info, warn};
+
+#[cfg(unix)]
+use filespider::directories::remove_stale_socket;
+use filespider::directories::{get_filespider_directory, lock_filespider_directory};
+use filespider::document::sync::{self, LocalReplica};
+use filespider::document::tags;
+use filespider::settings::Settings;
+use filespider::types::SyncReport;
+
+/// Syncs the documents of this FileSpider directory with another one.
+/// Refuses to run while FileSpider is running on either of them, on both machines
+/// FileSpider has to be closed for a sync with --serve and --connect.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// another FileSpider directory
+    #[arg()]
+    directory: Option<String>,
+
+    /// wait for syncs started with --connect on this Unix socket
+    #[cfg(unix)]
+    #[arg(long, conflicts_with = "directory")]
+    serve: Option<PathBuf>,
+
+    /// sync with an instance running with --serve
+    #[cfg(unix)]
+    #[arg(long, conflicts_with_all = ["directory", "serve"])]
+    connect: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Info)
+        .init();
+
+    let root = get_filespider_directory()?;
+    let _lock = lock_filespider_directory(&root)?;
+
+    // imported tags are normalized like FileSpider would
+    tags::configure(&Settings::load().await?.0.tags);
+
+    let local = LocalReplica::open(&root).await?;
+
+    if let Some(directory) = args.directory {
+        let _other_lock = lock_filespider_directory(&directory)?;
+        let other = LocalReplica::open(&directory).await?;
+        print_report(&sync::sync(&local, &other).await?);
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = args.serve {
+        remove_stale_socket(&path).await?;
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        info!("waiting for syncs on {}", path.display());
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let stream = match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            warn!("failed to accept a sync: {:?}", e);
+                            continue;
+                        }
+                    };
+                    // one at a time, so syncs don't interleave.
+                    // a broken connection only ends its own sync
+                    match sync::remote::serve(&local, stream).await {
+                        Ok(()) => info!("sync finished"),
+                        Err(e) => warn!("sync failed: {:?}", e),
+                    }
+                }
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = args.connect {
+        let remote = sync::remote::RemoteReplica::new(tokio::net::UnixStream::connect(path).await?);
+        print_report(&sync::sync(&local, &remote).await?);
+        return Ok(());
+    }
+
+    Err(eyre!("nothing to sync with"))
+}
+
+fn print_report(report: &SyncReport) {
+    for id in report.pulled.iter() {
+        println!("pulled {}", id);
+    }
+    for id in report.pushed.iter() {
+        println!("pushed {}", id);
+    }
+    for id in report.removed_locally.iter() {
+        println!("removed {} locally", id);
+    }
+    for id in report.removed_remotely.iter() {
+        println!("removed {} remotely", id);
+    }
+    for id in report.conflict_copies.iter() {
+        println!("created conflict copy {}", id);
+    }
+}
@@ -0,0 +1,38 @@
+use clap::Parser;
+use eyre::Result;
+
+use filespider::settings::Settings;
+use filespider::types::StorageBackend;
+use filespider::{directories, document::storage};
+
+/// Moves all documents to another storage backend and selects it in the settings.
+/// Don't run this while FileSpider is running.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// directory or blob
+    #[arg()]
+    backend: StorageBackend,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Info)
+        .init();
+
+    directories::create_directories().await?;
+
+    let (mut settings, _) = Settings::load().await?;
+
+    storage::transfer(&settings.storage, &args.backend).await?;
+
+    settings.storage = args.backend;
+    settings.save().await?;
+
+    println!("storage backend is now {}", settings.storage);
+
+    Ok(())
+}
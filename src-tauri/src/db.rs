@@ -1,22 +1,108 @@
-use std::str::FromStr;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+use std::time::Duration;
 
 use eyre::Result;
-use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
+use sqlx::{query, SqlitePool};
+use uuid::Uuid;
 
 use crate::directories::get_filespider_directory;
+use crate::document::get_id_timestamp;
 
 pub async fn init() -> Result<SqlitePool> {
     std::fs::create_dir_all(get_filespider_directory()?)?;
 
     let db_path = std::env::var("DATABASE_URL")
         .unwrap_or(format!("{}/filespider.sqlite", get_filespider_directory()?));
+
+    open(&db_path).await
+}
+
+/// connects to and migrates the DB at `db_path`, e.g. of another filespider directory
+pub async fn open(db_path: &str) -> Result<SqlitePool> {
     println!("using DB {}", db_path);
-    let pool =
-        SqlitePool::connect_with(SqliteConnectOptions::from_str(&db_path)?.create_if_missing(true))
-            .await?;
+    let pool = SqlitePool::connect_with(
+        SqliteConnectOptions::from_str(db_path)?
+            .create_if_missing(true)
+            // readers don't wait for the writer, writers wait for each other instead of failing
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_secs(10))
+            .collation("natural", natural_cmp),
+    )
+    .await?;
 
     sqlx::migrate!().run(&pool).await?;
 
+    fix_import_timestamps(&pool).await?;
+
     Ok(pool)
 }
+
+/// `import_pdf` used to store a truncated millisecond count instead of a datetime,
+/// these documents get the creation time that is encoded in their v1 UUID instead
+async fn fix_import_timestamps(pool: &SqlitePool) -> Result<()> {
+    let ids = query!(
+        r#"select id as "id!: Uuid" from Document where typeof(added) = 'integer' or typeof(accessed) = 'integer'"#
+    )
+    .map(|x| x.id)
+    .fetch_all(pool)
+    .await?;
+
+    for id in ids {
+        let timestamp = get_id_timestamp(&id);
+
+        query!(
+            "update Document set added = ?, accessed = ? where id = ?",
+            timestamp,
+            timestamp,
+            id
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// the `natural` collation, compares case-insensitively and numbers by their value,
+/// so "lecture 9" comes before "Lecture 10"
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                x.len().cmp(&y.len()).then_with(|| x.cmp(&y))
+            }
+            (Some(&x), Some(&y)) => {
+                a.next();
+                b.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// the digits at the start of `chars` without leading zeros
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        if !(number.is_empty() && digit == '0') {
+            number.push(digit);
+        }
+    }
+    number
+}
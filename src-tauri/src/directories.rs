@@ -1,3 +1,8 @@
+#[cfg(unix)]
+use std::path::Path;
+
+#[cfg(unix)]
+use eyre::eyre;
 use eyre::Result;
 
 #[cfg(target_os = "linux")]
@@ -22,13 +27,90 @@ pub fn get_cache_directory() -> Result<String> {
     Ok(format!("{}/{}", get_filespider_directory()?, ".cache"))
 }
 
+/// new documents are written here before being moved into the filespider directory
+pub fn get_staging_directory() -> Result<String> {
+    Ok(format!("{}/{}", get_filespider_directory()?, ".staging"))
+}
+
+/// files of the presets that were saved from documents, the settings only refer to them
+pub fn get_presets_directory() -> Result<String> {
+    Ok(format!("{}/{}", get_filespider_directory()?, "presets"))
+}
+
 pub async fn create_directories() -> Result<()> {
     if !tokio::fs::try_exists(get_cache_directory()?).await? {
         tokio::fs::create_dir_all(get_cache_directory()?).await?;
     }
+    if !tokio::fs::try_exists(get_staging_directory()?).await? {
+        tokio::fs::create_dir_all(get_staging_directory()?).await?;
+    }
     Ok(())
 }
 
+/// held by FileSpider and tools like sync while they use the filespider directory `root`,
+/// the lock is released when the returned file is dropped, including when the process crashes
+#[cfg(unix)]
+pub fn lock_filespider_directory(root: &str) -> Result<std::fs::File> {
+    use std::os::fd::AsRawFd;
+
+    extern "C" {
+        fn flock(fd: i32, operation: i32) -> i32;
+    }
+    const LOCK_EX: i32 = 2;
+    const LOCK_NB: i32 = 4;
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(format!("{}/.lock", root))?;
+    if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return match e.kind() {
+            std::io::ErrorKind::WouldBlock => Err(eyre!(
+                "{} is in use, FileSpider or another tool is already running on it",
+                root
+            )),
+            _ => Err(e.into()),
+        };
+    }
+    Ok(file)
+}
+
+/// Windows doesn't let anyone else open a file that is opened without sharing
+#[cfg(windows)]
+pub fn lock_filespider_directory(root: &str) -> Result<std::fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .share_mode(0)
+        .open(format!("{}/.lock", root))
+        .map_err(|e| {
+            eyre::eyre!(
+                "{} is in use, FileSpider or another tool is already running on it: {}",
+                root,
+                e
+            )
+        })
+}
+
+/// removes a socket that a previous run left behind and that would make binding fail,
+/// anything else at `path` is kept so a mistyped path can't delete a file
+#[cfg(unix)]
+pub async fn remove_stale_socket(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.file_type().is_socket() => Ok(tokio::fs::remove_file(path).await?),
+        Ok(_) => Err(eyre!("{} exists and isn't a socket", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
 #[cfg(target_os = "windows")]
 pub fn get_filespider_directory() -> Result<String> {
     Ok(std::env::var("FILESPIDER_DATA_PATH").unwrap_or("%APPDATA%/filespider".to_string()))
@@ -0,0 +1,223 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use eyre::{eyre, Result, WrapErr};
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use regex::Regex;
+use sqlx::{query, SqlitePool};
+use uuid::Uuid;
+
+use crate::settings::TagRule;
+use crate::types::{DocType, TagRuleReport};
+
+use super::{apply_tag_rules, get_document_basename, get_meta, storage, tags};
+
+/// a [`TagRule`] with its patterns compiled
+struct Rule {
+    tags: Vec<String>,
+    doc_type: Option<DocType>,
+    title: Option<Regex>,
+    content: Option<Regex>,
+    source_folder: Option<PathBuf>,
+    metadata: Vec<(String, Regex)>,
+}
+
+static RULES: RwLock<Vec<Rule>> = RwLock::new(Vec::new());
+
+/// replaces the rules, they are kept if one of the new ones is invalid
+pub fn configure(rules: &[TagRule]) -> Result<()> {
+    *RULES.write().unwrap() = compile(rules)?;
+
+    Ok(())
+}
+
+/// checks that the patterns of the rules compile
+pub fn validate(rules: &[TagRule]) -> Result<()> {
+    compile(rules).map(|_| ())
+}
+
+fn compile(rules: &[TagRule]) -> Result<Vec<Rule>> {
+    let regex = |pattern: &Option<String>| pattern.as_deref().map(Regex::new).transpose();
+
+    rules
+        .iter()
+        .enumerate()
+        .map(|(i, rule)| {
+            let compiled: Result<Rule> = try {
+                let mut metadata = vec![];
+                for (field, pattern) in rule.metadata.iter() {
+                    if !["author", "subject", "keywords"].contains(&field.as_str()) {
+                        Err(eyre!("unknown metadata field {}", field))?;
+                    }
+                    metadata.push((field.clone(), Regex::new(pattern)?));
+                }
+
+                Rule {
+                    tags: rule.tags.clone(),
+                    doc_type: rule.doc_type.clone(),
+                    title: regex(&rule.title)?,
+                    content: regex(&rule.content)?,
+                    source_folder: rule.source_folder.as_ref().map(PathBuf::from),
+                    metadata,
+                }
+            };
+            compiled.wrap_err(format!("tag rule {} is invalid", i + 1))
+        })
+        .collect()
+}
+
+/// what the rules are matched against
+pub(super) struct Candidate<'a> {
+    pub title: &'a str,
+    pub doc_type: &'a DocType,
+    /// the main file, None if no rule needs it
+    pub content: Option<&'a [u8]>,
+    /// the file the document was created from
+    pub source: Option<&'a Path>,
+    pub author: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    /// joined by ", "
+    pub keywords: Option<&'a str>,
+}
+
+impl Candidate<'_> {
+    fn metadata(&self, field: &str) -> Option<&str> {
+        match field {
+            "author" => self.author,
+            "subject" => self.subject,
+            "keywords" => self.keywords,
+            _ => None,
+        }
+    }
+}
+
+/// reading the content is skipped otherwise
+pub(super) fn needs_content() -> bool {
+    RULES.read().unwrap().iter().any(|r| r.content.is_some())
+}
+
+/// the tags of all matching rules, they aren't normalized yet
+pub(super) fn matching_tags(candidate: &Candidate) -> Vec<String> {
+    let rules = RULES.read().unwrap();
+
+    let mut text = None;
+    let mut tags: Vec<String> = vec![];
+    for rule in rules.iter() {
+        let matches = rule
+            .doc_type
+            .as_ref()
+            .map_or(true, |t| t == candidate.doc_type)
+            && rule
+                .title
+                .as_ref()
+                .map_or(true, |r| r.is_match(candidate.title))
+            && rule.source_folder.as_ref().map_or(true, |folder| {
+                candidate.source.is_some_and(|s| s.starts_with(folder))
+            })
+            && rule.metadata.iter().all(|(field, r)| {
+                candidate
+                    .metadata(field)
+                    .is_some_and(|value| r.is_match(value))
+            })
+            && rule.content.as_ref().map_or(true, |r| {
+                text.get_or_insert_with(|| candidate.content.map(content_text))
+                    .as_ref()
+                    .is_some_and(|t| r.is_match(t))
+            });
+
+        if matches {
+            for tag in rule.tags.iter() {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+        }
+    }
+
+    tags
+}
+
+/// gzipped files like `.xopp` are unpacked, invalid UTF-8 is replaced
+pub(super) fn content_text(content: &[u8]) -> String {
+    let mut unpacked = vec![];
+    if content.starts_with(&[0x1f, 0x8b])
+        && GzDecoder::new(content).read_to_end(&mut unpacked).is_ok()
+    {
+        return String::from_utf8_lossy(&unpacked).into_owned();
+    }
+
+    String::from_utf8_lossy(content).into_owned()
+}
+
+/// canonical tags of matching rules that the document doesn't have yet
+pub(super) async fn missing_tags(pool: &SqlitePool, id: Uuid) -> Result<Vec<String>> {
+    if RULES.read().unwrap().is_empty() {
+        return Ok(vec![]);
+    }
+
+    let meta = get_meta(pool, id).await?;
+    let info = query!(
+        "select author, subject, keywords from PdfInfo where document = ?",
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let keywords = match info.as_ref().and_then(|i| i.keywords.as_deref()) {
+        Some(k) => Some(serde_json::from_str::<Vec<String>>(k)?.join(", ")),
+        None => None,
+    };
+    let content = match needs_content() {
+        true => Some(
+            storage::get()?
+                .read(&id, &get_document_basename(&id, &meta.extension))
+                .await?,
+        ),
+        false => None,
+    };
+
+    let matching = matching_tags(&Candidate {
+        title: &meta.title,
+        doc_type: &meta.doc_type,
+        content: content.as_deref(),
+        source: None,
+        author: info.as_ref().and_then(|i| i.author.as_deref()),
+        subject: info.as_ref().and_then(|i| i.subject.as_deref()),
+        keywords: keywords.as_deref(),
+    });
+
+    let matching = tags::canonical_all(&mut *pool.acquire().await?, matching).await?;
+    Ok(matching
+        .into_iter()
+        .filter(|t| !meta.tags.contains(t))
+        .collect())
+}
+
+/// re-applies the rules to every document, e.g. after they were changed.
+/// documents they can't be applied to are skipped and reported
+pub async fn apply_all(pool: &SqlitePool) -> Result<TagRuleReport> {
+    let documents = query!(r#"select id as "id!: Uuid" from Document"#)
+        .map(|x| x.id)
+        .fetch_all(pool)
+        .await?;
+
+    let mut report = TagRuleReport::default();
+    for id in documents {
+        match apply_tag_rules(pool, id).await {
+            Ok(tags) => report.added += tags.len() as u32,
+            Err(e) => {
+                warn!("failed to apply the tag rules to {}: {:?}", id, e);
+                report.failed.push((id, format!("{:?}", e)));
+            }
+        }
+    }
+
+    info!(
+        "tag rules added {} tags, {} documents failed",
+        report.added,
+        report.failed.len()
+    );
+
+    Ok(report)
+}
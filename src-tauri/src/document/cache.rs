@@ -0,0 +1,131 @@
+use eyre::Result;
+use log::info;
+use sqlx::{query, SqlitePool};
+use uuid::Uuid;
+
+use crate::directories::get_cache_directory;
+use crate::types::CacheUsage;
+
+/// marks the render of a document as used for the LRU eviction
+pub async fn touch(pool: &SqlitePool, id: Uuid) -> Result<()> {
+    let timestamp = chrono::Utc::now();
+
+    query!(
+        "update Cache set accessed = ? where document = ?",
+        timestamp,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// evicts the least recently used renders until the cache is smaller than `limit` bytes,
+/// the render of `keep` is never evicted, even if it alone exceeds the limit
+pub async fn evict(pool: &SqlitePool, limit: u64, keep: Option<Uuid>) -> Result<()> {
+    let mut size = get_cache_size().await?;
+    if size <= limit {
+        return Ok(());
+    }
+
+    let documents = query!(r#"select document as "document!: Uuid" from Cache order by accessed"#)
+        .map(|x| x.document)
+        .fetch_all(pool)
+        .await?;
+
+    for id in documents {
+        if size <= limit {
+            break;
+        }
+        if Some(id) == keep {
+            continue;
+        }
+        size = size.saturating_sub(remove(pool, id).await?);
+        info!("evicted render of {} from cache", id);
+    }
+
+    Ok(())
+}
+
+/// removes the cached renders of a document, returns the number of bytes freed
+pub async fn remove(pool: &SqlitePool, id: Uuid) -> Result<u64> {
+    query!("delete from Cache where document = ?", id)
+        .execute(pool)
+        .await?;
+
+    let mut freed = 0;
+    let prefix = id.to_string();
+
+    let mut entries = tokio::fs::read_dir(get_cache_directory()?).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            freed += entry.metadata().await?.len();
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+
+    Ok(freed)
+}
+
+/// removes renders of deleted documents and files that don't belong to any cache entry,
+/// then evicts renders like [`evict`] in case the limit was lowered
+pub async fn prune(pool: &SqlitePool, limit: u64) -> Result<()> {
+    query!("delete from Cache where document not in (select id from Document)")
+        .execute(pool)
+        .await?;
+
+    let documents: Vec<String> = query!(r#"select document as "document!: Uuid" from Cache"#)
+        .map(|x| x.document.to_string())
+        .fetch_all(pool)
+        .await?;
+
+    let mut entries = tokio::fs::read_dir(get_cache_directory()?).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !documents.iter().any(|id| name.starts_with(id)) {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+
+    evict(pool, limit, None).await
+}
+
+/// removes all renders, least recently used first so an interrupted clear keeps the useful ones
+pub async fn clear(pool: &SqlitePool) -> Result<()> {
+    evict(pool, 0, None).await?;
+
+    // entries whose files were already gone and files without an entry, e.g. thumbnails
+    query!("delete from Cache").execute(pool).await?;
+
+    let mut entries = tokio::fs::read_dir(get_cache_directory()?).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        tokio::fs::remove_file(entry.path()).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_usage(pool: &SqlitePool, limit: u64) -> Result<CacheUsage> {
+    let entries = query!(r#"select count(*) as "count!: u32" from Cache"#)
+        .fetch_one(pool)
+        .await?
+        .count;
+
+    Ok(CacheUsage {
+        size: get_cache_size().await?,
+        limit,
+        entries,
+    })
+}
+
+async fn get_cache_size() -> Result<u64> {
+    let mut size = 0;
+
+    let mut entries = tokio::fs::read_dir(get_cache_directory()?).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        size += entry.metadata().await?.len();
+    }
+
+    Ok(size)
+}
@@ -0,0 +1,190 @@
+use eyre::{eyre, Result};
+use sqlx::{query, SqliteConnection, SqlitePool};
+use uuid::Uuid;
+
+use crate::types::Collection;
+
+use super::document_exists;
+
+pub async fn create(pool: &SqlitePool, name: String) -> Result<()> {
+    query!("insert into Collection (name) values (?)", name)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_all(pool: &SqlitePool) -> Result<Vec<Collection>> {
+    let mut connection = pool.acquire().await?;
+    let mut collections = vec![];
+
+    for name in query!("select name from Collection order by name")
+        .map(|x| x.name)
+        .fetch_all(&mut *connection)
+        .await?
+    {
+        let documents = get_documents(&mut connection, &name).await?;
+        collections.push(Collection { name, documents });
+    }
+
+    Ok(collections)
+}
+
+pub async fn rename(pool: &SqlitePool, name: String, new_name: String) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    if query!(
+        "update Collection set name = ? where name = ?",
+        new_name,
+        name
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(eyre!("no collection named {}", name));
+    }
+
+    query!(
+        "update CollectionDocument set collection = ? where collection = ?",
+        new_name,
+        name
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// the documents are kept, only the collection is removed
+pub async fn delete(pool: &SqlitePool, name: String) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    if query!("delete from Collection where name = ?", name)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(eyre!("no collection named {}", name));
+    }
+
+    query!("delete from CollectionDocument where collection = ?", name)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// inserts the document before the one at `position`, None or a position past the end appends it
+pub async fn insert(
+    pool: &SqlitePool,
+    name: String,
+    id: Uuid,
+    position: Option<u32>,
+) -> Result<()> {
+    document_exists(&id).await?;
+
+    let mut tx = pool.begin().await?;
+
+    let mut documents = get_documents(&mut tx, &name).await?;
+    if documents.contains(&id) {
+        return Err(eyre!("document {} is already in collection {}", id, name));
+    }
+
+    let position = position.map_or(documents.len(), |p| documents.len().min(p as usize));
+    documents.insert(position, id);
+
+    write_order(&mut tx, &name, &documents).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn remove(pool: &SqlitePool, name: String, id: Uuid) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let mut documents = get_documents(&mut tx, &name).await?;
+    let Some(position) = documents.iter().position(|d| *d == id) else {
+        return Err(eyre!("document {} is not in collection {}", id, name));
+    };
+    documents.remove(position);
+
+    write_order(&mut tx, &name, &documents).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// `documents` has to contain exactly the documents already in the collection
+pub async fn reorder(pool: &SqlitePool, name: String, documents: Vec<Uuid>) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let mut current = get_documents(&mut tx, &name).await?;
+    let mut new = documents.clone();
+    current.sort();
+    new.sort();
+    if current != new {
+        return Err(eyre!(
+            "the new order doesn't contain the documents of collection {}",
+            name
+        ));
+    }
+
+    write_order(&mut tx, &name, &documents).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// the documents of a collection in order
+async fn get_documents(connection: &mut SqliteConnection, name: &str) -> Result<Vec<Uuid>> {
+    if query!("select name from Collection where name = ?", name)
+        .fetch_optional(&mut *connection)
+        .await?
+        .is_none()
+    {
+        return Err(eyre!("no collection named {}", name));
+    }
+
+    Ok(query!(
+        r#"select document as "document!: Uuid" from CollectionDocument where collection = ? order by position"#,
+        name
+    )
+    .map(|x| x.document)
+    .fetch_all(&mut *connection)
+    .await?)
+}
+
+/// positions are renumbered on every change, deleted documents may leave gaps until then
+async fn write_order(
+    connection: &mut SqliteConnection,
+    name: &str,
+    documents: &[Uuid],
+) -> Result<()> {
+    query!("delete from CollectionDocument where collection = ?", name)
+        .execute(&mut *connection)
+        .await?;
+
+    for (position, document) in documents.iter().enumerate() {
+        let position = position as i64;
+        query!(
+            "insert into CollectionDocument (collection, document, position) values (?, ?, ?)",
+            name,
+            document,
+            position
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use eyre::Result;
 use tauri::State;
 use tauri::{
@@ -19,9 +21,9 @@ pub async fn search(
     page: u32,
     page_length: u32,
     sort: SearchSorting,
-) -> Result<Vec<Meta>, String> {
+) -> Result<SearchResponse, String> {
     document::search(
-        &*state.pool.lock().await,
+        &state.pool,
         pos_filter,
         neg_filter,
         crib,
@@ -42,33 +44,76 @@ pub async fn create(
     extension: Option<String>,
     file: document::File,
 ) -> Result<Uuid, String> {
-    document::create(
-        &*state.pool.lock().await,
-        title,
-        doc_type,
-        tags,
-        extension,
-        file,
-    )
-    .await
-    .map_err(|x| format!("{x:?}"))
+    document::create(&state.pool, title, doc_type, tags, extension, file)
+        .await
+        .map_err(|x| format!("{x:?}"))
 }
 
+/// fills in the templates of the preset with `variables` and the builtin placeholders
 #[tauri::command]
-pub async fn import_pdf(
+pub async fn create_from_preset(
     state: State<'_, FilespiderState>,
+    preset: String,
     title: String,
     tags: Vec<String>,
+    variables: HashMap<String, String>,
+) -> Result<Uuid, String> {
+    let Some(preset) = state
+        .settings
+        .lock()
+        .await
+        .presets
+        .iter()
+        .find(|p| p.name == preset)
+        .cloned()
+    else {
+        return Err(format!("no preset named {}", preset));
+    };
+
+    document::template::create(&state.pool, &preset, title, tags, variables)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn import_pdf(
+    state: State<'_, FilespiderState>,
+    title: Option<String>,
+    tags: Vec<String>,
     file: document::File,
 ) -> Result<Uuid, String> {
-    document::import_pdf(&*state.pool.lock().await, title, tags, &file)
+    document::import_pdf(&state.pool, title, tags, &file)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn read_pdf_info(file: document::File) -> Result<PdfInfo, String> {
+    document::read_pdf_info(&file)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn get_pdf_info(state: State<'_, FilespiderState>, id: Uuid) -> Result<PdfInfo, String> {
+    document::get_pdf_info(&state.pool, id)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn get_outline(
+    state: State<'_, FilespiderState>,
+    id: Uuid,
+) -> Result<Vec<OutlineEntry>, String> {
+    document::get_outline(&state.pool, id)
         .await
         .map_err(|x| format!("{x:?}"))
 }
 
 #[tauri::command]
 pub async fn get_meta(state: State<'_, FilespiderState>, id: Uuid) -> Result<Meta, String> {
-    document::get_meta(&*state.pool.lock().await, id)
+    document::get_meta(&state.pool, id)
         .await
         .map_err(|x| format!("{x:?}"))
 }
@@ -78,20 +123,28 @@ pub async fn render(
     state: State<'_, FilespiderState>,
     id: Uuid,
 ) -> Result<(String, RenderType), String> {
-    document::render::render(
-        &*state.pool.lock().await,
-        &mut *state.renderers.lock().await,
-        id,
-    )
-    .await
-    .map_err(|x| format!("{x:?}"))
+    let cache_size_limit = state.settings.lock().await.cache_size_limit;
+    document::render::render(&state.pool, &state.renderers, id, cache_size_limit)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn thumbnail(
+    state: State<'_, FilespiderState>,
+    id: Uuid,
+) -> Result<(String, ThumbnailType), String> {
+    let cache_size_limit = state.settings.lock().await.cache_size_limit;
+    document::thumbnail::thumbnail(&state.pool, &state.renderers, id, cache_size_limit)
+        .await
+        .map_err(|x| format!("{x:?}"))
 }
 
 /// returns Ok(false) if editor is already running, if editor got spawned it returns Ok(true)
 #[tauri::command]
 pub async fn open_editor(state: State<'_, FilespiderState>, id: Uuid) -> Result<bool, String> {
     document::open_editor(
-        &*state.pool.lock().await,
+        &state.pool,
         &*state.settings.lock().await,
         &mut *state.editors.lock().await,
         id,
@@ -106,14 +159,14 @@ pub async fn alter_meta(
     id: Uuid,
     patch: MetaPatch,
 ) -> Result<(), String> {
-    document::patch_meta(&*state.pool.lock().await, id, patch)
+    document::patch_meta(&state.pool, id, patch)
         .await
         .map_err(|x| format!("{x:?}"))
 }
 
 #[tauri::command]
 pub async fn delete(state: State<'_, FilespiderState>, id: Uuid) -> Result<(), String> {
-    document::delete(&*state.pool.lock().await, id)
+    document::delete(&state.pool, id)
         .await
         .map_err(|x| format!("{x:?}"))
 }
@@ -123,7 +176,7 @@ pub async fn get_tags(
     state: State<'_, FilespiderState>,
     crib: String,
 ) -> Result<Vec<String>, String> {
-    document::get_tags(&*state.pool.lock().await, crib)
+    document::get_tags(&state.pool, crib)
         .await
         .map_err(|x| format!("{x:?}"))
 }
@@ -133,31 +186,306 @@ pub async fn show_render_in_explorer(
     state: State<'_, FilespiderState>,
     id: Uuid,
 ) -> Result<(), String> {
+    let cache_size_limit = state.settings.lock().await.cache_size_limit;
+
     #[cfg(target_os = "linux")]
-    return match state.dbus.lock().await.as_ref() {
-        None => Err("D-Bus not available".to_string()),
-        Some(dbus) => document::show_render_in_explorer(
-            &*state.pool.lock().await,
-            &mut *state.renderers.lock().await,
-            id,
-            dbus.clone(),
-        )
-        .await
-        .map_err(|x| format!("{x:?}")),
+    let Some(dbus) = state.dbus.lock().await.clone() else {
+        return Err("D-Bus not available".to_string());
     };
-    #[cfg(not(target_os = "linux"))]
-    document::show_render_in_explorer(
-        &*state.pool.lock().await,
-        &mut *state.renderers.lock().await,
+    #[cfg(target_os = "linux")]
+    return document::show_render_in_explorer(
+        &state.pool,
+        &state.renderers,
         id,
+        cache_size_limit,
+        dbus,
     )
     .await
-    .map_err(|x| format!("{x:?}"))
+    .map_err(|x| format!("{x:?}"));
+    #[cfg(not(target_os = "linux"))]
+    document::show_render_in_explorer(&state.pool, &state.renderers, id, cache_size_limit)
+        .await
+        .map_err(|x| format!("{x:?}"))
 }
 
 #[tauri::command]
 pub async fn update_accessed(state: State<'_, FilespiderState>, id: Uuid) -> Result<(), String> {
-    document::update_accessed(&*state.pool.lock().await, id)
+    document::update_accessed(&state.pool, id)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// only reports, repairs need the fsck binary while FileSpider isn't running
+#[tauri::command]
+pub async fn fsck(state: State<'_, FilespiderState>) -> Result<FsckReport, String> {
+    document::fsck::fsck(&state.pool, false)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn get_cache_usage(state: State<'_, FilespiderState>) -> Result<CacheUsage, String> {
+    document::cache::get_usage(&state.pool, state.settings.lock().await.cache_size_limit)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn clear_cache(state: State<'_, FilespiderState>) -> Result<(), String> {
+    document::cache::clear(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// newest entries first, e.g. for an activity feed
+#[tauri::command]
+pub async fn get_journal(
+    state: State<'_, FilespiderState>,
+    document: Option<Uuid>,
+    from: Option<chrono::DateTime<chrono::Utc>>,
+    to: Option<chrono::DateTime<chrono::Utc>>,
+    limit: u32,
+) -> Result<Vec<JournalEntry>, String> {
+    document::journal::get(&state.pool, document, from, to, limit)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// returns the document that changed, None if there was nothing to undo
+#[tauri::command]
+pub async fn undo(state: State<'_, FilespiderState>) -> Result<Option<Uuid>, String> {
+    document::history::undo(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// returns the document that changed, None if there was nothing to redo
+#[tauri::command]
+pub async fn redo(state: State<'_, FilespiderState>) -> Result<Option<Uuid>, String> {
+    document::history::redo(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn create_saved_search(
+    state: State<'_, FilespiderState>,
+    search: SavedSearch,
+) -> Result<(), String> {
+    document::saved_search::create(&state.pool, search)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn get_saved_searches(
+    state: State<'_, FilespiderState>,
+) -> Result<Vec<SavedSearch>, String> {
+    document::saved_search::get_all(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn update_saved_search(
+    state: State<'_, FilespiderState>,
+    name: String,
+    search: SavedSearch,
+) -> Result<(), String> {
+    document::saved_search::update(&state.pool, name, search)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn delete_saved_search(
+    state: State<'_, FilespiderState>,
+    name: String,
+) -> Result<(), String> {
+    document::saved_search::delete(&state.pool, name)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// page starts at 0
+#[tauri::command]
+pub async fn evaluate_saved_search(
+    state: State<'_, FilespiderState>,
+    name: String,
+    page: u32,
+    page_length: u32,
+) -> Result<Vec<Meta>, String> {
+    document::saved_search::evaluate(&state.pool, name, page, page_length)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn create_collection(
+    state: State<'_, FilespiderState>,
+    name: String,
+) -> Result<(), String> {
+    document::collection::create(&state.pool, name)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn get_collections(state: State<'_, FilespiderState>) -> Result<Vec<Collection>, String> {
+    document::collection::get_all(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn rename_collection(
+    state: State<'_, FilespiderState>,
+    name: String,
+    new_name: String,
+) -> Result<(), String> {
+    document::collection::rename(&state.pool, name, new_name)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn delete_collection(
+    state: State<'_, FilespiderState>,
+    name: String,
+) -> Result<(), String> {
+    document::collection::delete(&state.pool, name)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// appends the document if position is None
+#[tauri::command]
+pub async fn insert_into_collection(
+    state: State<'_, FilespiderState>,
+    name: String,
+    id: Uuid,
+    position: Option<u32>,
+) -> Result<(), String> {
+    document::collection::insert(&state.pool, name, id, position)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn remove_from_collection(
+    state: State<'_, FilespiderState>,
+    name: String,
+    id: Uuid,
+) -> Result<(), String> {
+    document::collection::remove(&state.pool, name, id)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn reorder_collection(
+    state: State<'_, FilespiderState>,
+    name: String,
+    documents: Vec<Uuid>,
+) -> Result<(), String> {
+    document::collection::reorder(&state.pool, name, documents)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn get_tag_aliases(state: State<'_, FilespiderState>) -> Result<Vec<TagAlias>, String> {
+    document::tags::get_aliases(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn add_tag_alias(
+    state: State<'_, FilespiderState>,
+    alias: String,
+    tag: String,
+) -> Result<(), String> {
+    document::tags::add_alias(&state.pool, alias, tag)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn remove_tag_alias(
+    state: State<'_, FilespiderState>,
+    alias: String,
+) -> Result<(), String> {
+    document::tags::remove_alias(&state.pool, alias)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// moves all documents from one tag to another, the old tag becomes an alias of the new one
+#[tauri::command]
+pub async fn merge_tags(
+    state: State<'_, FilespiderState>,
+    from: String,
+    to: String,
+) -> Result<(), String> {
+    document::tags::merge(&state.pool, from, to)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn get_tag_infos(state: State<'_, FilespiderState>) -> Result<Vec<TagInfo>, String> {
+    document::tags::get_all(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// creates the tag if it doesn't exist
+#[tauri::command]
+pub async fn set_tag_properties(
+    state: State<'_, FilespiderState>,
+    name: String,
+    properties: TagProperties,
+) -> Result<(), String> {
+    document::tags::set_properties(&state.pool, name, properties)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn rename_tag(
+    state: State<'_, FilespiderState>,
+    name: String,
+    new_name: String,
+) -> Result<(), String> {
+    document::tags::rename(&state.pool, name, new_name)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// removes the tag from all documents
+#[tauri::command]
+pub async fn delete_tag(state: State<'_, FilespiderState>, name: String) -> Result<(), String> {
+    document::tags::delete(&state.pool, name)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn apply_tag_rules(state: State<'_, FilespiderState>) -> Result<TagRuleReport, String> {
+    document::auto_tag::apply_all(&state.pool)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+/// tags that fit the document, ranked by the tags it already has and by its text
+#[tauri::command]
+pub async fn suggest_tags(
+    state: State<'_, FilespiderState>,
+    id: Uuid,
+    count: u32,
+) -> Result<Vec<TagSuggestion>, String> {
+    document::suggest::suggest(&state.pool, id, count)
         .await
         .map_err(|x| format!("{x:?}"))
 }
@@ -167,15 +495,48 @@ pub fn plugin<R: Runtime>() -> TauriPlugin<R> {
         .invoke_handler(tauri::generate_handler![
             search,
             create,
+            create_from_preset,
             import_pdf,
+            read_pdf_info,
+            get_pdf_info,
+            get_outline,
             get_meta,
             render,
+            thumbnail,
             open_editor,
             alter_meta,
             delete,
             get_tags,
             show_render_in_explorer,
             update_accessed,
+            fsck,
+            get_cache_usage,
+            clear_cache,
+            get_journal,
+            undo,
+            redo,
+            create_saved_search,
+            get_saved_searches,
+            update_saved_search,
+            delete_saved_search,
+            evaluate_saved_search,
+            create_collection,
+            get_collections,
+            rename_collection,
+            delete_collection,
+            insert_into_collection,
+            remove_from_collection,
+            reorder_collection,
+            get_tag_aliases,
+            add_tag_alias,
+            remove_tag_alias,
+            merge_tags,
+            get_tag_infos,
+            set_tag_properties,
+            rename_tag,
+            delete_tag,
+            apply_tag_rules,
+            suggest_tags,
         ])
         .build()
 }
@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use eyre::Result;
+use log::{info, warn};
+use sqlx::{query, Sqlite, SqlitePool, Transaction};
+use uuid::Uuid;
+
+use crate::directories::get_staging_directory;
+use crate::types::{DocType, FsckReport, JournalOperation};
+
+use super::{cache, get_document_basename, get_id_timestamp, storage};
+
+/// checks the DB against the document directories and itself, with `repair` every inconsistency found is fixed:
+/// orphaned directories are adopted as new documents, rows of missing documents and unusable rows are pruned,
+/// missing tag properties are created and the cache is cleared. repairing needs FileSpider to be stopped
+pub async fn fsck(pool: &SqlitePool, repair: bool) -> Result<FsckReport> {
+    let documents: HashSet<Uuid> = query!(r#"select id as "id!: Uuid" from Document"#)
+        .map(|x| x.id)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+
+    let directories = storage::get()?.list_documents().await?;
+
+    let mut report = FsckReport {
+        orphaned_directories: directories.difference(&documents).copied().collect(),
+        missing_directories: documents.difference(&directories).copied().collect(),
+        dangling_tags: query!(
+            r#"select document as "document!: Uuid", tag from Tag where document not in (select id from Document)"#
+        )
+        .map(|x| (x.document, x.tag))
+        .fetch_all(pool)
+        .await?,
+        dangling_cache_entries: query!(
+            r#"select document as "document!: Uuid" from Cache where document not in (select id from Document)"#
+        )
+        .map(|x| x.document)
+        .fetch_all(pool)
+        .await?,
+        dangling_pdf_info: query!(
+            r#"select document as "document!: Uuid" from PdfInfo where document not in (select id from Document)
+            union select document as "document!: Uuid" from Outline where document not in (select id from Document)"#
+        )
+        .map(|x| x.document)
+        .fetch_all(pool)
+        .await?,
+        dangling_collection_entries: query!(
+            r#"select collection, document as "document!: Uuid" from CollectionDocument
+            where document not in (select id from Document) or collection not in (select name from Collection)"#
+        )
+        .map(|x| (x.collection, x.document))
+        .fetch_all(pool)
+        .await?,
+        invalid_journal_entries: query!("select seq, operation from Journal")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .filter(|x| serde_json::from_str::<JournalOperation>(&x.operation).is_err())
+            .map(|x| x.seq)
+            .collect(),
+        stale_history_entries: stale_history_entries(pool, &documents).await?,
+        inconsistent_tag_info: query!(
+            r#"select distinct tag as "tag!" from Tag where tag not in (select name from TagInfo)
+            union select name as "tag!" from TagInfo where name in (select alias from TagAlias)"#
+        )
+        .map(|x| x.tag)
+        .fetch_all(pool)
+        .await?,
+        invalid_sync_log_entries: query!(
+            r#"select document as "document!: Uuid" from SyncLog where peer in (select id from Replica)"#
+        )
+        .map(|x| x.document)
+        .fetch_all(pool)
+        .await?,
+        stale_staging_directories: list_staging_directories().await?,
+        repaired: false,
+    };
+
+    report.orphaned_directories.sort();
+    report.missing_directories.sort();
+
+    if repair && !report.is_clean() {
+        let mut tx = pool.begin().await?;
+
+        for id in report.orphaned_directories.iter() {
+            adopt_directory(&mut tx, id).await?;
+            info!("adopted orphaned directory of document {}", id);
+        }
+
+        for id in report.missing_directories.iter() {
+            query!("delete from Document where id = ?", id)
+                .execute(&mut *tx)
+                .await?;
+            warn!("pruned document {} whose directory is missing", id);
+        }
+
+        query!("delete from Tag where document not in (select id from Document)")
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from PdfInfo where document not in (select id from Document)")
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from Outline where document not in (select id from Document)")
+            .execute(&mut *tx)
+            .await?;
+        query!(
+            "delete from CollectionDocument where document not in (select id from Document)
+            or collection not in (select name from Collection)"
+        )
+        .execute(&mut *tx)
+        .await?;
+        for seq in report.invalid_journal_entries.iter() {
+            query!("delete from Journal where seq = ?", seq)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for seq in report.stale_history_entries.iter() {
+            query!("delete from History where seq = ?", seq)
+                .execute(&mut *tx)
+                .await?;
+        }
+        query!("delete from TagInfo where name in (select alias from TagAlias)")
+            .execute(&mut *tx)
+            .await?;
+        query!("insert or ignore into TagInfo (name) select distinct tag from Tag")
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from SyncLog where peer in (select id from Replica)")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        // renders can always be regenerated
+        cache::clear(pool).await?;
+        clear_directory(&get_staging_directory()?).await?;
+
+        report.repaired = true;
+    }
+
+    Ok(report)
+}
+
+/// entries whose document is missing, undone deletions of documents that exist again
+/// and entries that can't be read. conflicting tag changes are left to [`super::history::undo`]
+async fn stale_history_entries(pool: &SqlitePool, documents: &HashSet<Uuid>) -> Result<Vec<i64>> {
+    Ok(query!(
+        r#"select seq, stack, document as "document!: Uuid", operation from History order by seq"#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .filter(|x| {
+        let exists = documents.contains(&x.document);
+        match serde_json::from_str(&x.operation) {
+            Ok(JournalOperation::Patch { .. }) => !exists,
+            Ok(JournalOperation::Delete(_)) => exists == (x.stack == "undo"),
+            _ => true,
+        }
+    })
+    .map(|x| x.seq)
+    .collect())
+}
+
+async fn list_staging_directories() -> Result<Vec<String>> {
+    let mut directories = vec![];
+
+    if !tokio::fs::try_exists(get_staging_directory()?).await? {
+        return Ok(directories);
+    }
+
+    let mut entries = tokio::fs::read_dir(get_staging_directory()?).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        directories.push(entry.file_name().to_string_lossy().to_string());
+    }
+    directories.sort();
+
+    Ok(directories)
+}
+
+/// inserts a document for stored files without a row, the doc type is guessed from the extension of the main file
+async fn adopt_directory(tx: &mut Transaction<'_, Sqlite>, id: &Uuid) -> Result<()> {
+    let mut extension = None;
+    let mut has_main_file = false;
+
+    for name in storage::get()?.list(id).await? {
+        let path = std::path::Path::new(&name);
+        if path.file_stem().map(|s| s.to_string_lossy()) == Some(id.to_string().into()) {
+            extension = path.extension().map(|s| s.to_string_lossy().to_string());
+            has_main_file = true;
+            break;
+        }
+    }
+
+    if !has_main_file {
+        storage::get()?
+            .write(id, &get_document_basename(id, &None), &[])
+            .await?;
+    }
+
+    let title = format!("Recovered {}", id);
+    let doc_type = extension
+        .as_deref()
+        .and_then(|e| DocType::from_str(e).ok())
+        .unwrap_or(DocType::Plain)
+        .to_string();
+    let timestamp = get_id_timestamp(id);
+
+    query!(
+        "insert into Document (id, title, type, added, file_extension, accessed) values (?, ?, ?, ?, ?, ?)",
+        id,
+        title,
+        doc_type,
+        timestamp,
+        extension,
+        timestamp,
+    )
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn clear_directory(path: &str) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            tokio::fs::remove_dir_all(entry.path()).await?;
+        } else {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
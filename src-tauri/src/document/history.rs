@@ -0,0 +1,301 @@
+use eyre::{eyre, Result};
+use log::{info, warn};
+use sqlx::{query, SqliteConnection, SqlitePool};
+use uuid::Uuid;
+
+use crate::types::{JournalOperation, MetaPatch};
+
+use super::{
+    apply_patch, delete_rows, document_exists, journal, restore_rows, storage, DeletedRows,
+};
+
+/// number of operations that can be undone, deleted documents are purged from the trash after that
+const HISTORY_LENGTH: i64 = 100;
+
+/// pushes an undoable operation, should be called with the transaction that makes the change.
+/// entries of the undo stack have lower sequence numbers than the ones of the redo stack,
+/// so the top of the undo stack is its highest and the top of the redo stack its lowest.
+/// deletions keep the `rows` that [`delete_rows`] removed
+pub(super) async fn record(
+    connection: &mut SqliteConnection,
+    id: Uuid,
+    operation: &JournalOperation,
+    rows: Option<&DeletedRows>,
+) -> Result<()> {
+    let operation = serde_json::to_string(operation)?;
+    let rows = rows.map(serde_json::to_string).transpose()?;
+
+    // a new operation makes the undone ones unreachable
+    query!("delete from History where stack = 'redo'")
+        .execute(&mut *connection)
+        .await?;
+
+    query!(
+        "insert into History (stack, document, operation, rows) values ('undo', ?, ?, ?)",
+        id,
+        operation,
+        rows
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// drops the oldest operations beyond [`HISTORY_LENGTH`]
+pub(super) async fn trim(pool: &SqlitePool) -> Result<()> {
+    let dropped = query!(
+        r#"select seq, document as "document!: Uuid", operation from History where stack = 'undo'
+        order by seq desc limit -1 offset ?"#,
+        HISTORY_LENGTH
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for entry in dropped {
+        query!("delete from History where seq = ?", entry.seq)
+            .execute(pool)
+            .await?;
+
+        if let JournalOperation::Delete(_) = serde_json::from_str(&entry.operation)? {
+            storage::get()?.purge(&entry.document).await?;
+            info!("purged document {} from the trash", entry.document);
+        }
+    }
+
+    Ok(())
+}
+
+/// rewrites the tag in the pending operations after it was renamed or merged into `new_tag`,
+/// operations on a deleted tag (None) are dropped. should be called with the transaction that changes the tag
+pub(super) async fn replace_tag(
+    connection: &mut SqliteConnection,
+    tag: &str,
+    new_tag: Option<&str>,
+) -> Result<()> {
+    let entries = query!("select seq, operation from History")
+        .fetch_all(&mut *connection)
+        .await?;
+
+    // whether the patch is kept
+    let replace = |patch: &mut MetaPatch| match patch {
+        MetaPatch::AddTag(t) | MetaPatch::RemoveTag(t) if t == tag => match new_tag {
+            Some(new_tag) => {
+                *t = new_tag.to_string();
+                true
+            }
+            None => false,
+        },
+        _ => true,
+    };
+
+    for entry in entries {
+        let mut operation: JournalOperation = serde_json::from_str(&entry.operation)?;
+        let keep = match &mut operation {
+            JournalOperation::Patch { patch, inverse } => replace(patch) && replace(inverse),
+            JournalOperation::Delete(meta) if meta.tags.iter().any(|t| t == tag) => {
+                meta.tags.retain(|t| t != tag);
+                if let Some(new_tag) = new_tag {
+                    if !meta.tags.iter().any(|t| t == new_tag) {
+                        meta.tags.push(new_tag.to_string());
+                    }
+                }
+                true
+            }
+            _ => true,
+        };
+
+        if !keep {
+            query!("delete from History where seq = ?", entry.seq)
+                .execute(&mut *connection)
+                .await?;
+            continue;
+        }
+
+        let rewritten = serde_json::to_string(&operation)?;
+        if rewritten != entry.operation {
+            query!(
+                "update History set operation = ? where seq = ?",
+                rewritten,
+                entry.seq
+            )
+            .execute(&mut *connection)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// reverts the last operation, returns the document it affected or None if there is nothing to undo.
+/// operations that can't be reverted anymore are dropped and the next one is reverted instead
+pub async fn undo(pool: &SqlitePool) -> Result<Option<Uuid>> {
+    let (entry, operation) = loop {
+        let Some(entry) = query!(
+            r#"select seq, document as "document!: Uuid", operation, rows from History where stack = 'undo'
+            order by seq desc limit 1"#
+        )
+        .fetch_optional(pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let operation: JournalOperation = serde_json::from_str(&entry.operation)?;
+        if !drop_if_stale(pool, entry.seq, entry.document, &operation, true).await? {
+            break (entry, operation);
+        }
+    };
+
+    let mut tx = pool.begin().await?;
+
+    match operation.clone() {
+        JournalOperation::Patch { patch, inverse } => {
+            apply_patch(&mut tx, entry.document, &inverse).await?;
+            journal::record(
+                &mut tx,
+                entry.document,
+                &JournalOperation::Patch {
+                    patch: inverse,
+                    inverse: patch,
+                },
+            )
+            .await?;
+        }
+        JournalOperation::Delete(meta) => {
+            let rows: DeletedRows = match entry.rows.as_deref() {
+                Some(rows) => serde_json::from_str(rows)?,
+                // deleted before the rows were kept
+                None => DeletedRows::default(),
+            };
+            restore_rows(&mut tx, &meta, &rows).await?;
+            journal::record(&mut tx, entry.document, &JournalOperation::Restore(meta)).await?;
+
+            storage::get()?.restore(&entry.document).await?;
+        }
+        _ => return Err(eyre!("operation can't be undone")),
+    }
+
+    query!("update History set stack = 'redo' where seq = ?", entry.seq)
+        .execute(&mut *tx)
+        .await?;
+
+    if let Err(e) = tx.commit().await {
+        if let JournalOperation::Delete(_) = operation {
+            storage::get()?.trash(&entry.document).await?;
+        }
+        return Err(e.into());
+    }
+
+    Ok(Some(entry.document))
+}
+
+/// applies the last undone operation again, returns the document it affected or None if there is nothing to redo.
+/// operations that can't be applied anymore are dropped like in [`undo`]
+pub async fn redo(pool: &SqlitePool) -> Result<Option<Uuid>> {
+    let (entry, operation) = loop {
+        let Some(entry) = query!(
+            r#"select seq, document as "document!: Uuid", operation from History where stack = 'redo'
+            order by seq limit 1"#
+        )
+        .fetch_optional(pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let operation: JournalOperation = serde_json::from_str(&entry.operation)?;
+        if !drop_if_stale(pool, entry.seq, entry.document, &operation, false).await? {
+            break (entry, operation);
+        }
+    };
+
+    let mut tx = pool.begin().await?;
+
+    let rows = match &operation {
+        JournalOperation::Patch { patch, .. } => {
+            apply_patch(&mut tx, entry.document, patch).await?;
+            None
+        }
+        JournalOperation::Delete(_) => Some(serde_json::to_string(
+            &delete_rows(&mut tx, entry.document).await?,
+        )?),
+        _ => return Err(eyre!("operation can't be redone")),
+    };
+    journal::record(&mut tx, entry.document, &operation).await?;
+
+    query!(
+        "update History set stack = 'undo', rows = ? where seq = ?",
+        rows,
+        entry.seq
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if let JournalOperation::Delete(_) = operation {
+        super::cache::remove(pool, entry.document).await?;
+        storage::get()?.trash(&entry.document).await?;
+    }
+
+    Ok(Some(entry.document))
+}
+
+/// removes an entry that conflicts with the current state, e.g. because a sync deleted its document
+/// or its tag was added again by hand, it would block the stack forever. returns whether it was dropped
+async fn drop_if_stale(
+    pool: &SqlitePool,
+    seq: i64,
+    id: Uuid,
+    operation: &JournalOperation,
+    undo: bool,
+) -> Result<bool> {
+    let exists = document_exists(&id).await.is_ok();
+
+    let applicable = match operation {
+        JournalOperation::Patch { patch, inverse } => {
+            let patch = if undo { inverse } else { patch };
+            exists
+                && match patch {
+                    MetaPatch::AddTag(tag) => !has_tag(pool, id, tag).await?,
+                    MetaPatch::RemoveTag(tag) => has_tag(pool, id, tag).await?,
+                    MetaPatch::ChangeTitle(_) => true,
+                }
+        }
+        // the document of an undone deletion is supposed to be missing
+        JournalOperation::Delete(_) => exists != undo,
+        _ => return Err(eyre!("operation can't be undone or redone")),
+    };
+    if applicable {
+        return Ok(false);
+    }
+
+    query!("delete from History where seq = ?", seq)
+        .execute(pool)
+        .await?;
+    warn!(
+        "dropped operation on document {} from the history, it can't be applied anymore",
+        id
+    );
+
+    // the trashed files of a deletion that can't be undone are never needed again
+    if let (JournalOperation::Delete(_), true) = (operation, undo) {
+        if let Err(e) = storage::get()?.purge(&id).await {
+            warn!("failed to purge document {} from the trash: {:?}", id, e);
+        }
+    }
+
+    Ok(true)
+}
+
+async fn has_tag(pool: &SqlitePool, id: Uuid, tag: &str) -> Result<bool> {
+    Ok(query!(
+        "select tag from Tag where document = ? and tag = ?",
+        id,
+        tag
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some())
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use sqlx::{query, SqliteConnection, SqlitePool};
+use uuid::Uuid;
+
+use crate::types::{JournalEntry, JournalOperation};
+
+/// appends to the journal, should be called with the transaction that makes the change
+pub async fn record(
+    connection: &mut SqliteConnection,
+    id: Uuid,
+    operation: &JournalOperation,
+) -> Result<()> {
+    let timestamp = chrono::Utc::now();
+    let operation = serde_json::to_string(operation)?;
+
+    query!(
+        "insert into Journal (time, document, operation) values (?, ?, ?)",
+        timestamp,
+        id,
+        operation
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+}
+
+/// newest entries first, every filter is optional
+pub async fn get(
+    pool: &SqlitePool,
+    document: Option<Uuid>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    limit: u32,
+) -> Result<Vec<JournalEntry>> {
+    query!(
+        r#"select seq, time, document as "document!: Uuid", operation from Journal
+        where (? is null or document = ?) and (? is null or time >= ?) and (? is null or time <= ?)
+        order by seq desc limit ?"#,
+        document,
+        document,
+        from,
+        from,
+        to,
+        to,
+        limit
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|x| {
+        Ok(JournalEntry {
+            seq: x.seq,
+            time: x.time,
+            document: x.document,
+            operation: serde_json::from_str(&x.operation)?,
+        })
+    })
+    .collect()
+}
@@ -1,47 +1,62 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::io::Write;
 use std::str::FromStr;
 #[cfg(target_os = "linux")]
 use std::sync::Arc;
-use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
+use async_recursion::async_recursion;
 use base64::prelude::*;
 use eyre::eyre;
 use eyre::Result;
-use flate2::write::GzEncoder;
-use flate2::Compression;
 use mac_address::get_mac_address;
 use pdf::file::FileOptions;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use sqlx::sqlite::SqliteRow;
-use sqlx::{query, Row, SqlitePool};
+use sqlx::query::Query;
+use sqlx::sqlite::{SqliteArguments, SqliteRow};
+use sqlx::{query, Row, Sqlite, SqliteConnection, SqlitePool};
 use tokio::process::Command;
-use tokio::sync::Mutex;
-use tokio::task::JoinHandle;
 use uuid::Uuid;
 
 use crate::directories::get_cache_directory;
-use crate::directories::get_filespider_directory;
-use crate::document::render::Hash;
 use crate::settings::Settings;
 use crate::types::*;
 
+pub mod auto_tag;
+pub mod cache;
+pub mod collection;
 pub mod commands;
+pub mod fsck;
+pub mod history;
+pub mod journal;
+pub mod pdf_import;
 pub mod render;
+pub mod saved_search;
+pub mod storage;
+pub mod suggest;
+pub mod sync;
+pub mod tags;
+pub mod template;
+pub mod thumbnail;
 
 #[cfg(test)]
 mod tests;
 
-async fn document_exists(id: &Uuid) -> Result<()> {
-    match tokio::fs::try_exists(get_document_directory(id)?).await {
+pub(crate) async fn document_exists(id: &Uuid) -> Result<()> {
+    match storage::get()?.exists(id).await {
         Ok(true) => Ok(()),
         _ => Err(eyre!("document does not exist")),
     }
 }
 
-pub fn get_document_directory(id: &Uuid) -> Result<String> {
-    Ok(format!("{}/{}", get_filespider_directory()?, id))
+/// the creation time encoded in a v1 document id, ids of other versions get the current time
+pub fn get_id_timestamp(id: &Uuid) -> chrono::DateTime<chrono::Utc> {
+    id.get_timestamp()
+        .and_then(|t| {
+            let (secs, nanos) = t.to_unix();
+            chrono::DateTime::from_timestamp(secs as i64, nanos)
+        })
+        .unwrap_or_else(chrono::Utc::now)
 }
 
 fn get_document_basename(id: &Uuid, extension: &Option<String>) -> String {
@@ -51,19 +66,15 @@ fn get_document_basename(id: &Uuid, extension: &Option<String>) -> String {
     }
 }
 
-fn get_document_file(id: &Uuid, extension: &Option<String>) -> Result<String> {
-    Ok(format!(
-        "{}/{}",
-        get_document_directory(id)?,
-        get_document_basename(id, extension)
-    ))
-}
-
 fn get_cache_file(id: Uuid) -> Result<String> {
     Ok(format!("{}/{}", get_cache_directory()?, id))
 }
 
-/// page starts at 0
+fn get_thumbnail_file(id: Uuid) -> Result<String> {
+    Ok(format!("{}/{}.thumb", get_cache_directory()?, id))
+}
+
+/// page starts at 0, saved searches can be used in the filters like tags, see [`saved_search::SAVED_SEARCH_PREFIX`]
 pub async fn search(
     pool: &SqlitePool,
     pos_filter: Vec<String>,
@@ -72,48 +83,439 @@ pub async fn search(
     page: u32,
     page_length: u32,
     sort: SearchSorting,
-) -> Result<Vec<Meta>> {
-    use SearchSortCriterium::*;
+) -> Result<SearchResponse> {
+    let filter = SearchFilter::new(pool, pos_filter, neg_filter, &crib, &sort, 0).await?;
+
+    let results =
+        sorted_metas(pool, &filter, &crib, page * page_length, page_length, &sort).await?;
+
+    let total = filter
+        .query(&format!(
+            "select count(Document.id) as count {}",
+            filter.clause
+        ))
+        .map(|x: SqliteRow| x.get("count"))
+        .fetch_one(pool)
+        .await?;
 
-    let query_str = format!(
-        "select id from Document left join (select document, count(tag) as tagCount from Tag where tag in {} group by document) as posTags on posTags.document = Document.id left join (select document, count(tag) as tagCount from Tag where tag in {} group by document) as negTags on negTags.document = document.id where {} and (negTags.tagCount = 0 or negTags.tagCount is null) and Document.title like ?  order by {} {} limit ?, ?",
-        if pos_filter.is_empty() { "()".to_string() } else { format!("(?{})", ", ?".repeat(pos_filter.len() - 1)) },
-        if neg_filter.is_empty() { "()".to_string() } else { format!("(?{})", ", ?".repeat(neg_filter.len() - 1)) },
-        if pos_filter.is_empty() { "(posTags.tagCount = ? or posTags.tagCount is null)" } else { "posTags.tagCount = ?" },
-        match sort.0 {
-            CreationTime => "Document.added",
-            AccessTime => "Document.accessed",
-            Title => "Document.title",
-        },
-        if sort.1 { "asc" } else { "desc" }
-    );
+    let tags = filter
+        .query(&format!(
+            "select tag, count(document) as count from Tag where document in (select Document.id {}) group by tag order by count desc, tag",
+            filter.clause
+        ))
+        .map(|x: SqliteRow| Facet {
+            value: x.get::<String, _>("tag"),
+            count: x.get("count"),
+        })
+        .fetch_all(pool)
+        .await?;
 
-    let mut query = sqlx::query(&query_str);
+    let doc_types = filter
+        .query(&format!(
+            "select Document.type as type, count(Document.id) as count {} group by Document.type order by count desc, Document.type",
+            filter.clause
+        ))
+        .map(|x: SqliteRow| (x.get::<String, _>("type"), x.get("count")))
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|(doc_type, count)| {
+            Ok(Facet {
+                value: DocType::from_str(&doc_type)?,
+                count,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let names: Vec<_> = tags.iter().map(|t| t.value.clone()).collect();
+    let tag_info = tags::get_info(pool, &names).await?;
+
+    Ok(SearchResponse {
+        results,
+        total,
+        tags,
+        doc_types,
+        tag_info,
+    })
+}
+
+/// a parameter of a query that is built at runtime
+enum Param {
+    Text(String),
+    Int(i64),
+}
 
-    for pos_tag in pos_filter.iter() {
-        query = query.bind(pos_tag);
+/// the `from` and `where` clauses of a search, shared by the query of the page and the ones of the facets
+struct SearchFilter {
+    clause: String,
+    params: Vec<Param>,
+}
+
+impl SearchFilter {
+    /// `depth` is the number of saved searches this search is nested in
+    #[async_recursion]
+    async fn new(
+        pool: &SqlitePool,
+        pos_filter: Vec<String>,
+        neg_filter: Vec<String>,
+        crib: &str,
+        sort: &[(SearchSortCriterium, bool)],
+        depth: u32,
+    ) -> Result<Self> {
+        let (pos_searches, pos_filter) = saved_search::split_filter(pos_filter);
+        let (neg_searches, neg_filter) = saved_search::split_filter(neg_filter);
+
+        let mut connection = pool.acquire().await?;
+        let pos_filter = tags::canonical_all(&mut connection, pos_filter).await?;
+        let neg_filter = tags::canonical_all(&mut connection, neg_filter).await?;
+        drop(connection);
+
+        let mut pos_results = vec![];
+        for name in pos_searches {
+            pos_results.push(saved_search::resolve(pool, &name, depth + 1).await?);
+        }
+        let mut neg_results = vec![];
+        for name in neg_searches {
+            neg_results.push(saved_search::resolve(pool, &name, depth + 1).await?);
+        }
+
+        let mut params = vec![];
+
+        // documents outside of a collection that is sorted by don't match
+        let mut joins = vec![];
+        for (i, (criterium, _)) in sort.iter().enumerate() {
+            if let SearchSortCriterium::CollectionOrder(name) = criterium {
+                joins.push(format!("join CollectionDocument as c{i} on c{i}.document = Document.id and c{i}.collection = ?"));
+                params.push(Param::Text(name.clone()));
+            }
+        }
+
+        params.extend(pos_filter.iter().cloned().map(Param::Text));
+        params.extend(neg_filter.iter().cloned().map(Param::Text));
+        params.push(Param::Int(pos_filter.len() as i64));
+        params.push(Param::Text(format!("%{}%", crib)));
+
+        let mut searches = vec![];
+        for (negated, (subquery, search_params)) in pos_results
+            .into_iter()
+            .map(|r| (false, r))
+            .chain(neg_results.into_iter().map(|r| (true, r)))
+        {
+            searches.push(format!(
+                "and Document.id {} {}",
+                if negated { "not in" } else { "in" },
+                subquery
+            ));
+            params.extend(search_params);
+        }
+
+        let clause = format!(
+            "from Document {} left join (select document, count(tag) as tagCount from Tag where tag in {} group by document) as posTags on posTags.document = Document.id left join (select document, count(tag) as tagCount from Tag where tag in {} group by document) as negTags on negTags.document = document.id where {} and (negTags.tagCount = 0 or negTags.tagCount is null) and Document.title like ? {}",
+            joins.join(" "),
+            placeholders(pos_filter.len()),
+            placeholders(neg_filter.len()),
+            if pos_filter.is_empty() { "(posTags.tagCount = ? or posTags.tagCount is null)" } else { "posTags.tagCount = ?" },
+            searches.join(" "),
+        );
+
+        Ok(Self { clause, params })
     }
-    for neg_tag in neg_filter.iter() {
-        query = query.bind(neg_tag);
+
+    /// binds the parameters of the filter, `sql` must contain [`SearchFilter::clause`] as its only parameters
+    fn query<'q>(&'q self, sql: &'q str) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+        bind_params(sqlx::query(sql), &self.params)
     }
+}
+
+fn bind_params<'q>(
+    mut query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    params: &'q [Param],
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    for param in params {
+        query = match param {
+            Param::Text(text) => query.bind(text),
+            Param::Int(int) => query.bind(int),
+        };
+    }
+    query
+}
+
+/// the metadata of a page of the documents matching `filter`, fetched with the same query
+async fn sorted_metas(
+    pool: &SqlitePool,
+    filter: &SearchFilter,
+    crib: &str,
+    offset: u32,
+    count: u32,
+    sort: &[(SearchSortCriterium, bool)],
+) -> Result<Vec<Meta>> {
+    sorted_rows(pool, filter, crib, offset, count, sort, META_COLUMNS)
+        .await?
+        .iter()
+        .map(meta_from_row)
+        .collect()
+}
 
-    query = query
-        .bind(pos_filter.len() as u32)
-        .bind(format!("%{}%", crib))
-        .bind(page * page_length)
-        .bind(page_length);
+async fn sorted_ids(
+    pool: &SqlitePool,
+    filter: &SearchFilter,
+    crib: &str,
+    offset: u32,
+    count: u32,
+    sort: &[(SearchSortCriterium, bool)],
+) -> Result<Vec<Uuid>> {
+    Ok(sorted_rows(pool, filter, crib, offset, count, sort, "")
+        .await?
+        .iter()
+        .map(|x| x.get("id"))
+        .collect())
+}
+
+/// the columns [`meta_from_row`] needs, tags are aggregated to a JSON array
+const META_COLUMNS: &str = ", Document.title as title, Document.type as type, Document.added as added, Document.file_extension as file_extension, Document.accessed as accessed, (select json_group_array(tag) from (select tag from Tag where Tag.document = Document.id order by tag)) as tags";
+
+fn meta_from_row(row: &SqliteRow) -> Result<Meta> {
+    Ok(Meta {
+        title: row.get("title"),
+        doc_type: DocType::from_str(row.get("type"))?,
+        tags: serde_json::from_str(row.get("tags"))?,
+        created: row.get("added"),
+        accessed: row.get("accessed"),
+        id: row.get("id"),
+        extension: row.get("file_extension"),
+    })
+}
+
+/// an SQL expression to sort by and its parameters
+type SortKey = (String, Vec<Param>);
 
-    let docs: Vec<Uuid> = query
-        .map(|x: SqliteRow| x.get("id"))
+/// the SQL expression and its parameters for every criterium, None for the ones only the storage knows
+fn sort_keys(sort: &[(SearchSortCriterium, bool)], crib: &str) -> Result<Vec<Option<SortKey>>> {
+    use SearchSortCriterium::*;
+
+    sort.iter()
+        .enumerate()
+        .map(|(i, (criterium, _))| {
+            let mut params = vec![];
+            let key = match criterium {
+                CreationTime => "Document.added".to_string(),
+                AccessTime => "Document.accessed".to_string(),
+                Title => "Document.title collate natural".to_string(),
+                DocType => "Document.type".to_string(),
+                TagCount => {
+                    "(select count(tag) from Tag where Tag.document = Document.id)".to_string()
+                }
+                Relevance => {
+                    params.extend(
+                        [
+                            crib.to_string(),
+                            format!("{}%", crib),
+                            format!("% {}%", crib),
+                        ]
+                        .map(Param::Text),
+                    );
+                    "case when Document.title like ? then 3 when Document.title like ? then 2 when Document.title like ? then 1 else 0 end".to_string()
+                }
+                MetadataField(field) => format!(
+                    "(select {} from PdfInfo where PdfInfo.document = Document.id) collate natural",
+                    match field.as_str() {
+                        "author" => "author",
+                        "subject" => "subject",
+                        "keywords" => "(select group_concat(value, ', ') from json_each(keywords))",
+                        _ => return Err(eyre!("unknown metadata field {}", field)),
+                    }
+                ),
+                // joined by the filter
+                CollectionOrder(_) => format!("c{i}.position"),
+                ModificationTime | FileSize => return Ok(None),
+            };
+            Ok(Some((key, params)))
+        })
+        .collect()
+}
+
+/// the terms of an `order by` for the keys of [`sort_keys`] that the DB knows, each followed by a comma
+fn order_by(
+    sort: &[(SearchSortCriterium, bool)],
+    keys: Vec<Option<SortKey>>,
+) -> (String, Vec<Param>) {
+    let mut order = String::new();
+    let mut params = vec![];
+    for ((_, ascending), (key, key_params)) in
+        sort.iter().zip(keys).flat_map(|(s, k)| Some((s, k?)))
+    {
+        order.push_str(&format!(
+            "{} {}, ",
+            key,
+            if *ascending { "asc" } else { "desc" }
+        ));
+        params.extend(key_params);
+    }
+    (order, params)
+}
+
+/// a page of the documents matching `filter` with the id and `columns`, which has to start with a comma
+#[allow(clippy::too_many_arguments)]
+async fn sorted_rows(
+    pool: &SqlitePool,
+    filter: &SearchFilter,
+    crib: &str,
+    offset: u32,
+    count: u32,
+    sort: &[(SearchSortCriterium, bool)],
+    columns: &str,
+) -> Result<Vec<SqliteRow>> {
+    use SearchSortCriterium::*;
+
+    let keys = sort_keys(sort, crib)?;
+    // the keys before the first one only the storage knows
+    let Some(known) = keys.iter().position(Option::is_none) else {
+        // the DB sorts and pages by itself, which is a lot faster than computing ranks
+        let (order, params) = order_by(sort, keys);
+        let query_str = format!(
+            "select Document.id as id{} {} order by {} Document.id limit ?, ?",
+            columns, filter.clause, order,
+        );
+        return Ok(bind_params(
+            bind_params(sqlx::query(&query_str), &filter.params),
+            &params,
+        )
+        .bind(offset)
+        .bind(count)
         .fetch_all(pool)
-        .await?;
+        .await?);
+    };
+
+    // every key the DB knows is selected as its rank so they can be mixed with the storage keys,
+    // the columns are only fetched for the page
+    let mut ranks = String::new();
+    let mut params = vec![];
+    for (i, (key, key_params)) in keys
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, k)| Some((i, k?)))
+    {
+        ranks.push_str(&format!(
+            ", dense_rank() over (order by {}) as key{}",
+            key, i
+        ));
+        params.extend(key_params);
+    }
 
-    futures::future::join_all(docs.into_iter().map(|id| get_meta(pool, id)))
-        .await
+    let query_str = format!("select Document.id as id{} {}", ranks, filter.clause);
+    let rows = bind_params(
+        bind_params(sqlx::query(&query_str), &params),
+        &filter.params,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut docs: Vec<(Uuid, Vec<i128>, SqliteRow)> = rows
         .into_iter()
+        .map(|row| {
+            let keys = sort
+                .iter()
+                .enumerate()
+                .map(|(i, (criterium, _))| match criterium {
+                    ModificationTime | FileSize => 0,
+                    _ => row.get::<i64, _>(format!("key{}", i).as_str()) as i128,
+                })
+                .collect();
+            (row.get("id"), keys, row)
+        })
+        .collect();
+
+    // compares the first `n` keys
+    let compare = |a: &[i128], b: &[i128], n: usize| {
+        sort[..n]
+            .iter()
+            .enumerate()
+            .map(|(i, (_, ascending))| match ascending {
+                true => a[i].cmp(&b[i]),
+                false => b[i].cmp(&a[i]),
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    };
+
+    docs.sort_by(|(_, a, _), (_, b, _)| compare(a, b, known));
+
+    let start = (offset as usize).min(docs.len());
+    let end = (offset as usize + count as usize).min(docs.len());
+    if start == end {
+        return Ok(vec![]);
+    }
+
+    // only the documents that are equal to the ones on the page in the known keys can end up on it,
+    // so only they are stat'ed and sorted by the remaining keys
+    let mut first = start;
+    while first > 0 && compare(&docs[first - 1].1, &docs[start].1, known).is_eq() {
+        first -= 1;
+    }
+    let mut last = end;
+    while last < docs.len() && compare(&docs[last].1, &docs[end - 1].1, known).is_eq() {
+        last += 1;
+    }
+
+    let storage = storage::get()?;
+    for (id, keys, _) in docs[first..last].iter_mut() {
+        let stat = storage.stat(id).await?;
+        for (i, (criterium, _)) in sort.iter().enumerate() {
+            match criterium {
+                ModificationTime => {
+                    keys[i] = stat.modified.duration_since(UNIX_EPOCH)?.as_millis() as i128
+                }
+                FileSize => keys[i] = stat.size as i128,
+                _ => {}
+            }
+        }
+    }
+    docs[first..last].sort_by(|(a_id, a, _), (b_id, b, _)| {
+        compare(a, b, sort.len()).then_with(|| a_id.cmp(b_id))
+    });
+
+    let page: Vec<(Uuid, SqliteRow)> = docs
+        .drain(start..end)
+        .map(|(id, _, row)| (id, row))
+        .collect();
+    if columns.is_empty() {
+        return Ok(page.into_iter().map(|(_, row)| row).collect());
+    }
+
+    let ids = serde_json::to_string(
+        &page
+            .iter()
+            .map(|(id, _)| id.simple().to_string())
+            .collect::<Vec<_>>(),
+    )?;
+    let mut rows: HashMap<Uuid, SqliteRow> = sqlx::query(&format!(
+        "select Document.id as id{} from Document where Document.id in (select unhex(value) from json_each(?))",
+        columns
+    ))
+    .bind(ids)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.get("id"), row))
+    .collect();
+
+    page.iter()
+        .map(|(id, _)| {
+            rows.remove(id)
+                .ok_or(eyre!("document {} was deleted while searching", id))
+        })
         .collect()
 }
 
+/// `(?, ?, ...)` with `n` parameters
+fn placeholders(n: usize) -> String {
+    if n == 0 {
+        "()".to_string()
+    } else {
+        format!("(?{})", ", ?".repeat(n - 1))
+    }
+}
+
 fn as_base64<T, S>(key: &T, serializer: S) -> Result<<S as Serializer>::Ok, S::Error>
 where
     T: AsRef<[u8]>,
@@ -140,6 +542,22 @@ pub enum File {
     Blob(Vec<u8>),
 }
 
+/// a document that is about to be written by [`create_document`]
+struct NewDocument {
+    title: String,
+    doc_type: DocType,
+    tags: Vec<String>,
+    extension: Option<String>,
+    file: File,
+    /// additional files in the document directory, by file name
+    attachments: Vec<(String, File)>,
+    pdf_info: Option<PdfInfo>,
+    /// the file the document is created from, for the tag rules
+    source: Option<String>,
+    /// values for the placeholders of a template, None copies the files unchanged
+    variables: Option<HashMap<String, String>>,
+}
+
 pub async fn create(
     pool: &SqlitePool,
     title: String,
@@ -148,118 +566,276 @@ pub async fn create(
     extension: Option<String>,
     file: File,
 ) -> Result<Uuid> {
-    let id: Uuid = Uuid::now_v1(&get_mac_address()?.map(|x| x.bytes()).unwrap_or([0x69u8; 6]));
+    let source = match &file {
+        File::Path(p) => Some(p.clone()),
+        _ => None,
+    };
+
+    create_document(
+        pool,
+        NewDocument {
+            title,
+            doc_type: doc_type.unwrap_or(DocType::Plain),
+            tags,
+            extension,
+            file,
+            attachments: vec![],
+            pdf_info: None,
+            source,
+            variables: None,
+        },
+    )
+    .await
+}
 
-    tokio::fs::create_dir(get_document_directory(&id)?).await?;
+fn new_document_id() -> Result<Uuid> {
+    Ok(Uuid::now_v1(
+        &get_mac_address()?.map(|x| x.bytes()).unwrap_or([0x69u8; 6]),
+    ))
+}
 
-    write_file_object_to_disk(&file, get_document_file(&id, &extension)?).await?;
+/// inserts the document in a transaction that is only committed once the storage backend
+/// has stored all files, so a failure leaves neither files nor rows behind
+async fn create_document(pool: &SqlitePool, doc: NewDocument) -> Result<Uuid> {
+    let id = new_document_id()?;
+
+    let mut files = vec![(
+        get_document_basename(&id, &doc.extension),
+        read_file_object(&doc.file).await?,
+    )];
+    for (name, file) in doc.attachments.iter() {
+        files.push((name.clone(), read_file_object(file).await?));
+    }
+    if let Some(variables) = &doc.variables {
+        for (_, content) in files.iter_mut() {
+            *content = template::fill(
+                std::mem::take(content),
+                id,
+                &doc.title,
+                &doc.tags,
+                variables,
+            );
+        }
+    }
 
-    let doc_type_str = doc_type.unwrap_or(DocType::Plain).to_string();
+    // rules match the keywords as one text, they are stored as a JSON array
+    let keywords = doc.pdf_info.as_ref().map(|i| i.keywords.join(", "));
+    let keywords_json = match doc.pdf_info.as_ref() {
+        Some(info) if !info.keywords.is_empty() => Some(serde_json::to_string(&info.keywords)?),
+        _ => None,
+    };
+    let mut doc_tags = doc.tags;
+    doc_tags.extend(auto_tag::matching_tags(&auto_tag::Candidate {
+        title: &doc.title,
+        doc_type: &doc.doc_type,
+        content: Some(&files[0].1),
+        source: doc.source.as_deref().map(std::path::Path::new),
+        author: doc.pdf_info.as_ref().and_then(|i| i.author.as_deref()),
+        subject: doc.pdf_info.as_ref().and_then(|i| i.subject.as_deref()),
+        keywords: keywords.as_deref(),
+    }));
+
+    let mut tx = pool.begin().await?;
+
+    let doc_tags = tags::canonical_all(&mut tx, doc_tags).await?;
+
+    let doc_type_str = doc.doc_type.to_string();
     let timestamp = chrono::Utc::now();
     query!(
         "insert into Document (id, title, type, added, file_extension, accessed) values (?, ?, ?, ?, ?, ?)",
         id,
-        title,
+        doc.title,
         doc_type_str,
         timestamp,
-        extension,
+        doc.extension,
         timestamp,
     )
-        .execute(pool)
+        .execute(&mut *tx)
         .await?;
 
-    for tag in tags {
+    for tag in doc_tags.iter() {
         query!("insert into Tag (document, tag) values (?, ?)", id, tag)
-            .execute(pool)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let meta = Meta {
+        title: doc.title,
+        doc_type: doc.doc_type,
+        tags: doc_tags,
+        created: timestamp.naive_utc(),
+        accessed: timestamp.naive_utc(),
+        id,
+        extension: doc.extension,
+    };
+    let operation = match doc.pdf_info {
+        Some(_) => JournalOperation::ImportPdf(meta),
+        None => JournalOperation::Create(meta),
+    };
+    journal::record(&mut tx, id, &operation).await?;
+
+    if let Some(info) = doc.pdf_info {
+        query!(
+            "insert into PdfInfo (document, author, subject, keywords) values (?, ?, ?, ?)",
+            id,
+            info.author,
+            info.subject,
+            keywords_json
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for (position, entry) in info.outline.iter().enumerate() {
+            let position = position as u32;
+            query!(
+                "insert into Outline (document, position, depth, title, page) values (?, ?, ?, ?, ?)",
+                id,
+                position,
+                entry.depth,
+                entry.title,
+                entry.page
+            )
+            .execute(&mut *tx)
             .await?;
+        }
     }
+
+    storage::get()?.insert(&id, files).await?;
+
+    if let Err(e) = tx.commit().await {
+        storage::get()?.delete(&id).await?;
+        return Err(e.into());
+    }
+
     Ok(id)
 }
 
-async fn write_file_object_to_disk(file: &File, dest: String) -> Result<(), eyre::Error> {
-    match file {
-        File::Path(path) => tokio::fs::copy(path, dest).await.map(|_| ()),
-        File::Blob(b) => tokio::fs::write(dest, b).await,
-        File::None => tokio::fs::write(dest, [0u8; 0]).await,
-    }?;
+async fn read_file_object(file: &File) -> Result<Vec<u8>> {
+    Ok(match file {
+        File::Path(path) => tokio::fs::read(path).await?,
+        File::Blob(b) => b.clone(),
+        File::None => vec![],
+    })
+}
+
+/// replaces the contents of the main file of a document
+pub async fn replace_file(pool: &SqlitePool, id: Uuid, file: File) -> Result<()> {
+    let meta = get_meta(pool, id).await?;
+    storage::get()?
+        .write(
+            &id,
+            &get_document_basename(&id, &meta.extension),
+            &read_file_object(&file).await?,
+        )
+        .await?;
+
+    journal::record(
+        &mut *pool.acquire().await?,
+        id,
+        &JournalOperation::ReplaceFile,
+    )
+    .await?;
+
+    apply_tag_rules(pool, id).await?;
+
     Ok(())
 }
 
 pub async fn import_pdf(
     pool: &SqlitePool,
-    title: String,
+    title: Option<String>,
     tags: Vec<String>,
     file: &File,
 ) -> Result<Uuid> {
     let pdf = FileOptions::cached().load(match file {
         File::None => return Err(eyre!("No file submitted")),
-        File::Path(p) => std::fs::read(p)?,
+        File::Path(p) => tokio::fs::read(p).await?,
         File::Blob(b) => b.clone(),
     })?;
 
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-
-    encoder.write_all(b"<xournal fileversion=\"4\">")?;
-
-    for (i, page) in pdf.pages().enumerate() {
-        let page = page?;
-        let crop_box = page.crop_box()?;
-
-        let w = if page.rotate == 0 || page.rotate == 180 {
-            crop_box.right - crop_box.left
-        } else {
-            crop_box.top - crop_box.bottom
-        }
-        .abs();
-        let h = if page.rotate == 0 || page.rotate == 180 {
-            crop_box.top - crop_box.bottom
-        } else {
-            crop_box.right - crop_box.left
-        }
-        .abs();
-
-        encoder.write_all(format!("<page width=\"{w}\" height=\"{h}\"><background type=\"pdf\" pageno=\"{}\" {}/><layer/></page>",
-                                  i + 1, if i == 0 { "domain=\"absolute\" filename=\"bg.pdf\"" } else { "" },
-        ).as_bytes())?;
-    }
-
-    encoder.write_all(b"</xournal>")?;
+    let info = pdf_import::read_info(&pdf)?;
+    let xopp_contents = pdf_import::xopp_from_pdf(&pdf)?;
+
+    let title = title
+        .or(info.title.clone())
+        .or(match file {
+            File::Path(p) => std::path::Path::new(p)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .unwrap_or("Untitled".to_string());
+
+    create_document(
+        pool,
+        NewDocument {
+            title,
+            doc_type: DocType::XournalPP,
+            tags,
+            extension: Some("xopp".to_string()),
+            file: File::Blob(xopp_contents),
+            attachments: vec![("bg.pdf".to_string(), file.clone())],
+            pdf_info: Some(info),
+            source: match file {
+                File::Path(p) => Some(p.clone()),
+                _ => None,
+            },
+            variables: None,
+        },
+    )
+    .await
+}
 
-    let xopp_contents = encoder.finish()?;
+/// reads the metadata of a PDF without importing it, e.g. to prefill the import dialog
+pub async fn read_pdf_info(file: &File) -> Result<PdfInfo> {
+    let pdf = FileOptions::cached().load(match file {
+        File::None => return Err(eyre!("No file submitted")),
+        File::Path(p) => tokio::fs::read(p).await?,
+        File::Blob(b) => b.clone(),
+    })?;
 
-    let extension = Some("xopp".to_string());
+    pdf_import::read_info(&pdf)
+}
 
-    let id: Uuid = Uuid::now_v1(&get_mac_address()?.map(|x| x.bytes()).unwrap_or([0x69u8; 6]));
-    tokio::fs::create_dir(get_document_directory(&id)?).await?;
+/// returns the metadata that was extracted when the PDF was imported
+pub async fn get_pdf_info(pool: &SqlitePool, id: Uuid) -> Result<PdfInfo> {
+    document_exists(&id).await?;
 
-    write_file_object_to_disk(
-        file,
-        format!("{}/{}", get_document_directory(&id)?, "bg.pdf"),
+    let info = query!(
+        "select title, author, subject, keywords from Document join PdfInfo on PdfInfo.document = Document.id where id = ?",
+        id
     )
-    .await?;
+    .fetch_optional(pool)
+    .await?
+    .ok_or(eyre!("document was not imported from a PDF"))?;
+
+    Ok(PdfInfo {
+        title: Some(info.title),
+        author: info.author,
+        subject: info.subject,
+        keywords: info
+            .keywords
+            .map(|k| serde_json::from_str(&k))
+            .transpose()?
+            .unwrap_or_default(),
+        outline: get_outline(pool, id).await?,
+    })
+}
 
-    tokio::fs::write(get_document_file(&id, &extension)?, xopp_contents).await?;
+pub async fn get_outline(pool: &SqlitePool, id: Uuid) -> Result<Vec<OutlineEntry>> {
+    document_exists(&id).await?;
 
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u32;
-    let doc_type = DocType::XournalPP.to_string();
-    query!(
-        "insert into Document (id, title, type, added, file_extension, accessed) values (?, ?, ?, ?, ?, ?)",
-        id,
-        title,
-        doc_type,
-        timestamp,
-        extension,
-        timestamp
+    Ok(query!(
+        "select title, depth, page from Outline where document = ? order by position",
+        id
     )
-        .execute(pool)
-        .await?;
-
-    for tag in tags {
-        query!("insert into Tag (document, tag) values (?, ?)", id, tag)
-            .execute(pool)
-            .await?;
-    }
-    Ok(id)
+    .map(|x| OutlineEntry {
+        title: x.title,
+        depth: x.depth as u32,
+        page: x.page.map(|p| p as u32),
+    })
+    .fetch_all(pool)
+    .await?)
 }
 
 pub async fn get_meta(pool: &SqlitePool, id: Uuid) -> Result<Meta> {
@@ -306,6 +882,11 @@ pub async fn open_editor(
     }
 
     let meta = get_meta(pool, id).await?;
+    let file = format!(
+        "{}/{}",
+        storage::get()?.local_directory(&id).await?,
+        get_document_basename(&id, &meta.extension)
+    );
 
     editors.insert(
         id,
@@ -315,12 +896,7 @@ pub async fn open_editor(
                     .get_editor(settings)
                     .1
                     .iter()
-                    .map(|s| {
-                        s.replace(
-                            "%FILE%",
-                            &get_document_file(&meta.id, &meta.extension).unwrap(),
-                        )
-                    })
+                    .map(|s| s.replace("%FILE%", &file))
                     .collect::<Vec<_>>(),
             )
             .spawn()?,
@@ -329,85 +905,273 @@ pub async fn open_editor(
     Ok(true)
 }
 
+/// a new title can match other tag rules
 pub async fn patch_meta(pool: &SqlitePool, id: Uuid, patch: MetaPatch) -> Result<()> {
+    let retag = matches!(patch, MetaPatch::ChangeTitle(_));
+
+    record_patch(pool, id, patch).await?;
+
+    if retag {
+        apply_tag_rules(pool, id).await?;
+    }
+
+    Ok(())
+}
+
+/// adds the tags of matching rules that the document doesn't have yet and returns them
+async fn apply_tag_rules(pool: &SqlitePool, id: Uuid) -> Result<Vec<String>> {
+    let tags = auto_tag::missing_tags(pool, id).await?;
+    for tag in tags.iter() {
+        record_patch(pool, id, MetaPatch::AddTag(tag.clone())).await?;
+    }
+
+    Ok(tags)
+}
+
+async fn record_patch(pool: &SqlitePool, id: Uuid, patch: MetaPatch) -> Result<()> {
     document_exists(&id).await?;
 
-    match patch {
+    let mut tx = pool.begin().await?;
+
+    // tags are compared in their normalized form, so "Math" is a duplicate of "math"
+    let patch = tags::canonical_patch(&mut tx, patch).await?;
+    let inverse = apply_patch(&mut tx, id, &patch).await?;
+    let operation = JournalOperation::Patch { patch, inverse };
+    journal::record(&mut tx, id, &operation).await?;
+    history::record(&mut tx, id, &operation, None).await?;
+
+    tx.commit().await?;
+
+    history::trim(pool).await
+}
+
+/// returns the patch that reverts it
+async fn apply_patch(
+    connection: &mut SqliteConnection,
+    id: Uuid,
+    patch: &MetaPatch,
+) -> Result<MetaPatch> {
+    Ok(match patch {
         MetaPatch::ChangeTitle(title) => {
+            let previous = query!("select title from Document where id = ?", id)
+                .fetch_one(&mut *connection)
+                .await?
+                .title;
+
             match query!("update Document set title = ? where id = ?", title, id)
-                .execute(pool)
+                .execute(&mut *connection)
                 .await?
                 .rows_affected()
             {
-                1 => Ok(()),
-                _ => Err(eyre!("Wrong number of rows affected")),
+                1 => MetaPatch::ChangeTitle(previous),
+                _ => return Err(eyre!("Wrong number of rows affected")),
             }
         }
         MetaPatch::AddTag(tag) => {
-            match query!("insert into Tag (document, tag) values (?, ?)", id, tag)
-                .execute(pool)
-                .await?
-                .rows_affected()
+            match query!(
+                "insert or ignore into Tag (document, tag) values (?, ?)",
+                id,
+                tag
+            )
+            .execute(&mut *connection)
+            .await?
+            .rows_affected()
             {
-                0 => Err(eyre!("document already has tag")),
-                1 => Ok(()),
+                0 => return Err(eyre!("document already has tag")),
+                1 => MetaPatch::RemoveTag(tag.clone()),
                 _ => panic!(),
             }
         }
         MetaPatch::RemoveTag(tag) => {
             match query!("delete from Tag where tag = ? and document = ?", tag, id)
-                .execute(pool)
+                .execute(&mut *connection)
                 .await?
                 .rows_affected()
             {
-                1 => Ok(()),
-                0 => Err(eyre!("failed to delete tag")),
+                1 => MetaPatch::AddTag(tag.clone()),
+                0 => return Err(eyre!("failed to delete tag")),
                 _ => panic!(),
             }
         }
-    }
+    })
 }
 
+/// the files are moved to the trash, so the deletion can be undone until it drops out of the history
 pub async fn delete(pool: &SqlitePool, id: Uuid) -> Result<()> {
     document_exists(&id).await?;
 
-    if query!(
-        "delete from Document where id = ?; delete from Tag where document = ?",
-        id,
-        id
-    )
-    .execute(pool)
-    .await?
-    .rows_affected()
+    let meta = get_meta(pool, id).await?;
+
+    let mut tx = pool.begin().await?;
+
+    let operation = JournalOperation::Delete(meta);
+    let rows = delete_rows(&mut tx, id).await?;
+    journal::record(&mut tx, id, &operation).await?;
+    history::record(&mut tx, id, &operation, Some(&rows)).await?;
+
+    tx.commit().await?;
+
+    cache::remove(pool, id).await?;
+
+    storage::get()?.trash(&id).await?;
+
+    history::trim(pool).await
+}
+
+/// rows of other tables that belonged to a deleted document
+#[derive(Serialize, Deserialize, Default)]
+struct DeletedRows {
+    /// author, subject and keywords
+    pdf_info: Option<(Option<String>, Option<String>, Option<String>)>,
+    /// position, depth, title and page
+    outline: Vec<(i64, i64, String, Option<i64>)>,
+    /// collection and position
+    collections: Vec<(String, i64)>,
+}
+
+/// returns the rows that [`restore_rows`] needs to bring the document back
+async fn delete_rows(connection: &mut SqliteConnection, id: Uuid) -> Result<DeletedRows> {
+    let rows = DeletedRows {
+        pdf_info: query!(
+            "select author, subject, keywords from PdfInfo where document = ?",
+            id
+        )
+        .map(|x| (x.author, x.subject, x.keywords))
+        .fetch_optional(&mut *connection)
+        .await?,
+        outline: query!(
+            "select position, depth, title, page from Outline where document = ? order by position",
+            id
+        )
+        .map(|x| (x.position, x.depth, x.title, x.page))
+        .fetch_all(&mut *connection)
+        .await?,
+        collections: query!(
+            "select collection, position from CollectionDocument where document = ?",
+            id
+        )
+        .map(|x| (x.collection, x.position))
+        .fetch_all(&mut *connection)
+        .await?,
+    };
+
+    if query!("delete from Document where id = ?", id)
+        .execute(&mut *connection)
+        .await?
+        .rows_affected()
         == 0
     {
         return Err(eyre!("no rows affected"));
     }
 
-    tokio::fs::remove_dir_all(get_document_directory(&id)?).await?;
+    query!("delete from Tag where document = ?", id)
+        .execute(&mut *connection)
+        .await?;
+    query!("delete from PdfInfo where document = ?", id)
+        .execute(&mut *connection)
+        .await?;
+    query!("delete from Outline where document = ?", id)
+        .execute(&mut *connection)
+        .await?;
+    query!("delete from CollectionDocument where document = ?", id)
+        .execute(&mut *connection)
+        .await?;
+
+    Ok(rows)
+}
+
+/// inserts the rows of a deleted document again, collections that were deleted in the meantime are skipped
+async fn restore_rows(
+    connection: &mut SqliteConnection,
+    meta: &Meta,
+    rows: &DeletedRows,
+) -> Result<()> {
+    let doc_type = meta.doc_type.to_string();
+    let added = meta.created.and_utc();
+    let accessed = meta.accessed.and_utc();
+
+    query!(
+        "insert into Document (id, title, type, added, file_extension, accessed) values (?, ?, ?, ?, ?, ?)",
+        meta.id,
+        meta.title,
+        doc_type,
+        added,
+        meta.extension,
+        accessed,
+    )
+        .execute(&mut *connection)
+        .await?;
+
+    for tag in meta.tags.iter() {
+        query!(
+            "insert into Tag (document, tag) values (?, ?)",
+            meta.id,
+            tag
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    if let Some((author, subject, keywords)) = &rows.pdf_info {
+        query!(
+            "insert into PdfInfo (document, author, subject, keywords) values (?, ?, ?, ?)",
+            meta.id,
+            author,
+            subject,
+            keywords
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for (position, depth, title, page) in rows.outline.iter() {
+        query!(
+            "insert into Outline (document, position, depth, title, page) values (?, ?, ?, ?, ?)",
+            meta.id,
+            position,
+            depth,
+            title,
+            page
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for (collection, position) in rows.collections.iter() {
+        query!(
+            "insert into CollectionDocument (collection, document, position)
+            select name, ?, ? from Collection where name = ?",
+            meta.id,
+            position,
+            collection
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
 
     Ok(())
 }
 
 pub async fn get_tags(pool: &SqlitePool, crib: String) -> Result<Vec<String>> {
     Ok(query!(
-        "select distinct tag from Tag where tag like '%' || ? || '%'",
+        "select name from TagInfo where name like '%' || ? || '%' order by pinned desc, name",
         crib
     )
-    .map(|x| x.tag)
+    .map(|x| x.name)
     .fetch_all(pool)
     .await?)
 }
 
 pub async fn show_render_in_explorer(
     pool: &SqlitePool,
-    renderers: &mut HashMap<(Uuid, Hash), Mutex<JoinHandle<()>>>,
+    renderers: &render::Renderers,
     id: Uuid,
+    cache_size_limit: u64,
     #[cfg(target_os = "linux")] dbus: Arc<dbus::nonblock::SyncConnection>,
 ) -> Result<()> {
     document_exists(&id).await?;
 
-    let render = render::render(pool, renderers, id).await?;
+    let render = render::render(pool, renderers, id, cache_size_limit).await?;
 
     #[cfg(target_os = "linux")]
     {
@@ -467,13 +1231,19 @@ pub async fn update_accessed(pool: &SqlitePool, id: Uuid) -> Result<()> {
 
     let timestamp = chrono::Utc::now();
 
+    let mut tx = pool.begin().await?;
+
     query!(
         "update Document set accessed = ? where id = ?",
         timestamp,
         id
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
 
+    journal::record(&mut tx, id, &JournalOperation::Access).await?;
+
+    tx.commit().await?;
+
     Ok(())
 }
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use std::io::Write;
+
+use eyre::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use fxhash::FxHashMap;
+use pdf::file::CachedFile;
+use pdf::object::{Action, MaybeNamedDest, Object, OutlineItem, PlainRef, Ref, Resolve};
+use pdf::primitive::PdfString;
+
+use crate::types::{OutlineEntry, PdfInfo};
+
+/// outlines of broken PDFs can be arbitrarily deep, everything below this is dropped
+const MAX_OUTLINE_DEPTH: u32 = 32;
+
+pub fn read_info(pdf: &CachedFile<Vec<u8>>) -> Result<PdfInfo> {
+    let info = pdf.trailer.info_dict.as_ref();
+
+    let string = |s: Option<&PdfString>| {
+        s.map(|s| s.to_string_lossy().trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    Ok(PdfInfo {
+        title: string(info.and_then(|i| i.title.as_ref())),
+        author: string(info.and_then(|i| i.author.as_ref())),
+        subject: string(info.and_then(|i| i.subject.as_ref())),
+        keywords: string(info.and_then(|i| i.keywords.as_ref()))
+            .map(|k| split_keywords(&k))
+            .unwrap_or_default(),
+        outline: read_outline(pdf)?,
+    })
+}
+
+/// keywords are free text, most producers separate them by commas or semicolons, some only by spaces
+pub(super) fn split_keywords(keywords: &str) -> Vec<String> {
+    let mut parts: Vec<&str> = keywords.split([',', ';']).collect();
+    if parts.len() == 1 {
+        parts = keywords.split_whitespace().collect();
+    }
+
+    let mut res: Vec<String> = vec![];
+    for part in parts {
+        let part = part.trim().to_string();
+        if !part.is_empty() && !res.contains(&part) {
+            res.push(part);
+        }
+    }
+    res
+}
+
+fn read_outline(pdf: &CachedFile<Vec<u8>>) -> Result<Vec<OutlineEntry>> {
+    let Some(outlines) = pdf.get_root().outlines.as_ref() else {
+        return Ok(vec![]);
+    };
+
+    let mut pages = FxHashMap::default();
+    for (i, page) in pdf.pages().enumerate() {
+        pages.insert(page?.get_ref().get_inner(), i as u32);
+    }
+
+    let resolver = pdf.resolver();
+    let mut entries = vec![];
+    let mut visited = HashSet::new();
+
+    // (item, depth), children are pushed after their next sibling so they get popped first
+    let mut stack: Vec<(Ref<OutlineItem>, u32)> = outlines.first.iter().map(|r| (*r, 0)).collect();
+
+    while let Some((item_ref, depth)) = stack.pop() {
+        if depth > MAX_OUTLINE_DEPTH || !visited.insert(item_ref.get_inner()) {
+            continue;
+        }
+        let item = resolver.get(item_ref)?;
+
+        entries.push(OutlineEntry {
+            title: item
+                .title
+                .as_ref()
+                .map(|t| t.to_string_lossy())
+                .unwrap_or_default(),
+            depth,
+            page: outline_item_page(&item, &resolver, &pages),
+        });
+
+        if let Some(next) = item.next {
+            stack.push((next, depth));
+        }
+        if let Some(first) = item.first {
+            stack.push((first, depth + 1));
+        }
+    }
+
+    Ok(entries)
+}
+
+/// returns None for named destinations and destinations that don't point to a page of this PDF
+fn outline_item_page(
+    item: &OutlineItem,
+    resolver: &impl Resolve,
+    pages: &FxHashMap<PlainRef, u32>,
+) -> Option<u32> {
+    let dest = match (&item.dest, &item.action) {
+        (Some(dest), _) => MaybeNamedDest::from_primitive(dest.clone(), resolver).ok()?,
+        (None, Some(Action::Goto(dest))) => dest.clone(),
+        _ => return None,
+    };
+
+    match dest {
+        MaybeNamedDest::Direct(dest) => pages.get(&dest.page?.get_inner()).copied(),
+        MaybeNamedDest::Named(_) => None,
+    }
+}
+
+/// creates a gzipped xournal++ document with one page per PDF page, using `bg.pdf` as background
+pub fn xopp_from_pdf(pdf: &CachedFile<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+    encoder.write_all(b"<xournal fileversion=\"4\">")?;
+
+    for (i, page) in pdf.pages().enumerate() {
+        let page = page?;
+        let crop_box = page.crop_box()?;
+
+        let w = if page.rotate == 0 || page.rotate == 180 {
+            crop_box.right - crop_box.left
+        } else {
+            crop_box.top - crop_box.bottom
+        }
+        .abs();
+        let h = if page.rotate == 0 || page.rotate == 180 {
+            crop_box.top - crop_box.bottom
+        } else {
+            crop_box.right - crop_box.left
+        }
+        .abs();
+
+        encoder.write_all(format!("<page width=\"{w}\" height=\"{h}\"><background type=\"pdf\" pageno=\"{}\" {}/><layer/></page>",
+                                  i + 1, if i == 0 { "domain=\"absolute\" filename=\"bg.pdf\"" } else { "" },
+        ).as_bytes())?;
+    }
+
+    encoder.write_all(b"</xournal>")?;
+
+    Ok(encoder.finish()?)
+}
@@ -1,19 +1,14 @@
-use std::{
-    collections::HashMap,
-    hash::Hasher,
-    path::{Path, PathBuf},
-    str::FromStr,
-    time::UNIX_EPOCH,
-};
+use std::{collections::HashMap, path::Path, str::FromStr};
 
-use async_recursion::async_recursion;
 use async_trait::async_trait;
 use eyre::eyre;
 use eyre::Result;
 use eyre::WrapErr;
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
 use sqlx::{query, SqliteConnection, SqlitePool};
 use tokio::io::AsyncWriteExt;
-use tokio::{sync::Mutex, task::JoinHandle};
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
 use crate::{
@@ -21,60 +16,34 @@ use crate::{
     types::{DocType, Meta, RenderType},
 };
 
-use super::{get_cache_file, get_document_basename, get_document_directory, get_document_file};
+use super::{cache, get_cache_file, get_document_basename, storage};
 
 pub type Hash = u64;
 
-pub async fn hash_document_files(id: Uuid) -> Result<Hash> {
-    document::document_exists(&id).await?;
-
-    let mut hasher = fxhash::FxHasher::default();
-    hash_file(&mut hasher, PathBuf::from(get_document_directory(&id)?)).await?;
-    Ok(hasher.finish())
-}
+/// a render in progress, everyone requesting the same render awaits it, the error is the panic message
+pub type RenderTask = Shared<BoxFuture<'static, Result<(), String>>>;
 
-#[async_recursion]
-async fn hash_file(hasher: &mut fxhash::FxHasher, path: PathBuf) -> Result<()> {
-    let meta = tokio::fs::metadata(&path).await?;
+/// renders in progress by document and hash of its files. the lock is only held to look up
+/// and insert tasks, so a long render doesn't hold up other renders or anything else
+pub type Renderers = Mutex<HashMap<(Uuid, Hash), RenderTask>>;
 
-    if meta.is_dir() {
-        let mut entries = tokio::fs::read_dir(&path).await?;
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            hash_file(hasher, entry.path()).await?;
-        }
-    } else {
-        hasher.write_u128(meta.modified()?.duration_since(UNIX_EPOCH)?.as_millis());
-    }
+pub async fn hash_document_files(id: Uuid) -> Result<Hash> {
+    document::document_exists(&id).await?;
 
-    Ok(())
+    storage::get()?.hash(&id).await
 }
 
+/// `cache_size_limit` is in bytes, least recently used renders of other documents are evicted to stay below it
 pub async fn render(
     pool: &SqlitePool,
-    renderers: &mut HashMap<(Uuid, Hash), Mutex<JoinHandle<()>>>,
+    renderers: &Renderers,
     id: Uuid,
+    cache_size_limit: u64,
 ) -> Result<(String, RenderType)> {
     document::document_exists(&id).await?;
     let hash = hash_document_files(id).await?;
     let hex_hash = format!("{:016x}", hash);
 
-    if let Some(handle) = renderers.get(&(id, hash)) {
-        if !handle.lock().await.is_finished() {
-            panic!("weird things happening: original renderer is not being awaited");
-        }
-        return get_from_cache(
-            id,
-            query!(
-                "select render_type from Cache where document = ? and hash = unhex(?)",
-                id,
-                hex_hash
-            )
-            .map(|r| RenderType::from_str(r.render_type.as_str()))
-            .fetch_one(pool)
-            .await??,
-        );
-    }
-
     // check cache
 
     if let Some(render_type) = query!(
@@ -86,46 +55,61 @@ pub async fn render(
     .fetch_optional(pool)
     .await?
     {
+        cache::touch(pool, id).await?;
         return get_from_cache(id, RenderType::from_str(render_type.as_str())?);
     }
 
-    let meta = document::get_meta(pool, id).await?;
-
-    let connection = pool.acquire().await?.detach();
-
-    let handle = tokio::task::spawn(render_task(meta, hash, connection));
-    let mutex = Mutex::new(handle);
-    renderers.insert((id, hash), mutex);
+    let running = renderers.lock().await.get(&(id, hash)).cloned();
+    let task = match running {
+        Some(task) => task,
+        None => {
+            let meta = document::get_meta(pool, id).await?;
 
-    let handle_ref = renderers.get(&(id, hash)).unwrap().lock().await;
-
-    // TODO is there really no better way to do this
-    while !handle_ref.is_finished() {
-        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-    }
+            // someone else may have started the same render in the meantime
+            renderers
+                .lock()
+                .await
+                .entry((id, hash))
+                .or_insert_with(|| {
+                    tokio::task::spawn(render_task(meta, hash, pool.clone()))
+                        .map(|r| r.map_err(|e| e.to_string()))
+                        .boxed()
+                        .shared()
+                })
+                .clone()
+        }
+    };
 
-    drop(handle_ref);
+    let finished = task.await;
+    renderers.lock().await.remove(&(id, hash));
+    finished.map_err(|e| eyre!("renderer failed: {}", e))?;
 
-    get_from_cache(
+    let render_type = query!(
+        "select render_type from Cache where document = ? and hash = unhex(?)",
         id,
-        query!(
-            "select render_type from Cache where document = ? and hash = unhex(?)",
-            id,
-            hex_hash
-        )
-        .map(|r| RenderType::from_str(r.render_type.as_str()))
-        .fetch_one(pool)
-        .await
-        .wrap_err("renderer didn't insert into cache")??,
+        hex_hash
     )
+    .map(|r| RenderType::from_str(r.render_type.as_str()))
+    .fetch_one(pool)
+    .await
+    .wrap_err("renderer didn't insert into cache")??;
+
+    cache::evict(pool, cache_size_limit, Some(id)).await?;
+
+    get_from_cache(id, render_type)
 }
 
 fn get_from_cache(id: Uuid, render_type: RenderType) -> Result<(String, RenderType)> {
     Ok((get_cache_file(id)?, render_type))
 }
 
+/// the connection is taken from the pool inside the task, so it goes back once the render is done
 #[allow(clippy::unused_io_amount)]
-async fn render_task(meta: Meta, hash: Hash, mut connection: SqliteConnection) {
+async fn render_task(meta: Meta, hash: Hash, pool: SqlitePool) {
+    let mut connection = pool
+        .acquire()
+        .await
+        .expect("failed to get a DB connection for the renderer");
     let renderer = get_renderer_from_doc_type(&meta.doc_type);
     if let Err(e) = renderer.render(meta.id, hash, &mut connection, &meta).await {
         if let Ok(true) = tokio::fs::try_exists(get_cache_file(meta.id).unwrap()).await {
@@ -156,14 +140,17 @@ async fn insert_into_cache<'a>(
 ) -> Result<()> {
     let hex_hash = format!("{:016x}", hash);
     let render_str = render_type.to_string();
+    let timestamp = chrono::Utc::now();
 
     query!(
-        "insert into Cache (document, hash, render_type) values (?, unhex(?), ?) on conflict(document) do update set hash = unhex(?), render_type = ?",
+        "insert into Cache (document, hash, render_type, accessed) values (?, unhex(?), ?, ?) on conflict(document) do update set hash = unhex(?), render_type = ?, accessed = ?, thumbnail = null",
         id,
         hex_hash,
         render_str,
+        timestamp,
         hex_hash,
-        render_str
+        render_str,
+        timestamp
     ).execute(connection).await?;
     Ok(())
 }
@@ -192,28 +179,16 @@ fn get_renderer_from_doc_type(doc_type: &DocType) -> Box<dyn Renderer + Send + S
 }
 
 async fn copy_into_tempdir(id: &Uuid, temp_path: &Path) -> Result<()> {
-    if tokio::process::Command::new("bash")
-        .args(vec![
-            "-c",
-            &format!(
-                "cp {}/* {}",
-                get_document_directory(id)?,
-                temp_path.to_str().unwrap()
-            ),
-        ])
-        .spawn()?
-        .wait()
-        .await?
-        .success()
-    {
-        Ok(())
-    } else {
-        Err(eyre!("copying exited with code != 0"))
-    }
-    .wrap_err("copying into tempdir failed")
+    storage::export(storage::get()?, id, temp_path)
+        .await
+        .wrap_err("copying into tempdir failed")
 }
 
-async fn execute_command(command: &str, args: Vec<&str>, current_dir: Option<&Path>) -> Result<()> {
+pub(super) async fn execute_command(
+    command: &str,
+    args: Vec<&str>,
+    current_dir: Option<&Path>,
+) -> Result<()> {
     match tokio::process::Command::new(command)
         .args(args)
         .current_dir(current_dir.unwrap_or(Path::new("/")))
@@ -258,14 +233,15 @@ impl Renderer for PlainRenderer {
         connection: &mut SqliteConnection,
         meta: &Meta,
     ) -> Result<()> {
-        copy_into_cache(
-            connection,
-            id,
-            hash,
-            get_document_file(&meta.id, &meta.extension)?,
-            RenderType::Plain,
+        tokio::fs::write(
+            get_cache_file(id)?,
+            storage::get()?
+                .read(&id, &get_document_basename(&meta.id, &meta.extension))
+                .await?,
         )
-        .await?;
+        .await
+        .wrap_err("copying into cache failed")?;
+        insert_into_cache(connection, id, hash, RenderType::Plain).await?;
         Ok(())
     }
 }
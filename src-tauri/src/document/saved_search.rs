@@ -0,0 +1,209 @@
+use eyre::{eyre, Result};
+use sqlx::{query, SqlitePool};
+
+use crate::types::{Meta, SavedSearch};
+
+use super::{order_by, sort_keys, sorted_ids, sorted_metas, Param, SearchFilter};
+
+/// a filter entry like `search:recent` matches the results of the saved search named `recent`
+pub const SAVED_SEARCH_PREFIX: &str = "search:";
+
+/// saved searches can reference each other, this stops cycles
+const MAX_DEPTH: u32 = 8;
+
+pub async fn create(pool: &SqlitePool, search: SavedSearch) -> Result<()> {
+    let pos_filter = serde_json::to_string(&search.pos_filter)?;
+    let neg_filter = serde_json::to_string(&search.neg_filter)?;
+    let sort = serde_json::to_string(&search.sort)?;
+
+    query!(
+        "insert into SavedSearch (name, pos_filter, neg_filter, crib, sort, result_limit) values (?, ?, ?, ?, ?, ?)",
+        search.name,
+        pos_filter,
+        neg_filter,
+        search.crib,
+        sort,
+        search.limit
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_all(pool: &SqlitePool) -> Result<Vec<SavedSearch>> {
+    query!("select name, pos_filter, neg_filter, crib, sort, result_limit from SavedSearch order by name")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|x| {
+            Ok(SavedSearch {
+                name: x.name,
+                pos_filter: serde_json::from_str(&x.pos_filter)?,
+                neg_filter: serde_json::from_str(&x.neg_filter)?,
+                crib: x.crib,
+                sort: serde_json::from_str(&x.sort)?,
+                limit: x.result_limit.map(|l| l as u32),
+            })
+        })
+        .collect()
+}
+
+pub async fn get(pool: &SqlitePool, name: &str) -> Result<SavedSearch> {
+    let x = query!(
+        "select name, pos_filter, neg_filter, crib, sort, result_limit from SavedSearch where name = ?",
+        name
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(eyre!("no saved search named {}", name))?;
+
+    Ok(SavedSearch {
+        name: x.name,
+        pos_filter: serde_json::from_str(&x.pos_filter)?,
+        neg_filter: serde_json::from_str(&x.neg_filter)?,
+        crib: x.crib,
+        sort: serde_json::from_str(&x.sort)?,
+        limit: x.result_limit.map(|l| l as u32),
+    })
+}
+
+/// replaces the saved search `name`, which is renamed if `search` has another name
+pub async fn update(pool: &SqlitePool, name: String, search: SavedSearch) -> Result<()> {
+    let pos_filter = serde_json::to_string(&search.pos_filter)?;
+    let neg_filter = serde_json::to_string(&search.neg_filter)?;
+    let sort = serde_json::to_string(&search.sort)?;
+
+    match query!(
+        "update SavedSearch set name = ?, pos_filter = ?, neg_filter = ?, crib = ?, sort = ?, result_limit = ? where name = ?",
+        search.name,
+        pos_filter,
+        neg_filter,
+        search.crib,
+        sort,
+        search.limit,
+        name
+    )
+    .execute(pool)
+    .await?
+    .rows_affected()
+    {
+        1 => Ok(()),
+        _ => Err(eyre!("no saved search named {}", name)),
+    }
+}
+
+pub async fn delete(pool: &SqlitePool, name: String) -> Result<()> {
+    match query!("delete from SavedSearch where name = ?", name)
+        .execute(pool)
+        .await?
+        .rows_affected()
+    {
+        1 => Ok(()),
+        _ => Err(eyre!("no saved search named {}", name)),
+    }
+}
+
+/// page starts at 0, pages end at the limit of the saved search
+pub async fn evaluate(
+    pool: &SqlitePool,
+    name: String,
+    page: u32,
+    page_length: u32,
+) -> Result<Vec<Meta>> {
+    let search = get(pool, &name).await?;
+
+    let offset = page * page_length;
+    let count = match search.limit {
+        Some(limit) => page_length.min(limit.saturating_sub(offset)),
+        None => page_length,
+    };
+
+    let filter = SearchFilter::new(
+        pool,
+        search.pos_filter,
+        search.neg_filter,
+        &search.crib,
+        &search.sort,
+        0,
+    )
+    .await?;
+
+    sorted_metas(pool, &filter, &search.crib, offset, count, &search.sort).await
+}
+
+/// separates the names of saved searches from the tags
+pub(super) fn split_filter(filter: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let (searches, tags): (Vec<_>, Vec<_>) = filter
+        .into_iter()
+        .partition(|f| f.starts_with(SAVED_SEARCH_PREFIX));
+
+    (
+        searches
+            .into_iter()
+            .map(|s| s[SAVED_SEARCH_PREFIX.len()..].to_string())
+            .collect(),
+        tags,
+    )
+}
+
+/// a subquery selecting the documents matching a saved search that is used as a filter, with its parameters
+pub(super) async fn resolve(
+    pool: &SqlitePool,
+    name: &str,
+    depth: u32,
+) -> Result<(String, Vec<Param>)> {
+    if depth > MAX_DEPTH {
+        return Err(eyre!(
+            "saved search {} is nested too deeply, is it referencing itself?",
+            name
+        ));
+    }
+
+    let search = get(pool, name).await?;
+
+    let filter = SearchFilter::new(
+        pool,
+        search.pos_filter,
+        search.neg_filter,
+        &search.crib,
+        &search.sort,
+        depth,
+    )
+    .await?;
+    let mut params = filter.params;
+
+    let Some(limit) = search.limit else {
+        return Ok((format!("(select Document.id {})", filter.clause), params));
+    };
+
+    let keys = sort_keys(&search.sort, &search.crib)?;
+    if keys.iter().any(Option::is_none) {
+        // only the storage knows which documents are within the limit, they are bound as one JSON array
+        let filter = SearchFilter {
+            clause: filter.clause,
+            params,
+        };
+        let ids: Vec<String> = sorted_ids(pool, &filter, &search.crib, 0, limit, &search.sort)
+            .await?
+            .iter()
+            .map(|id| id.simple().to_string())
+            .collect();
+        return Ok((
+            "(select unhex(value) from json_each(?))".to_string(),
+            vec![Param::Text(serde_json::to_string(&ids)?)],
+        ));
+    }
+
+    let (order, order_params) = order_by(&search.sort, keys);
+    params.extend(order_params);
+    params.push(Param::Int(limit as i64));
+
+    Ok((
+        format!(
+            "(select Document.id {} order by {} Document.id limit ?)",
+            filter.clause, order
+        ),
+        params,
+    ))
+}
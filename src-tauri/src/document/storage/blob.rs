@@ -0,0 +1,526 @@
+use std::collections::{BTreeMap, HashSet};
+use std::hash::Hasher;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::document::render::Hash;
+
+use super::{FileStat, Storage};
+
+/// content addressed store, files are kept once per distinct content in the blob directory
+/// and every document has a manifest mapping its file names to the SHA-256 of their content.
+///
+/// external editors can't change blobs, so [`Storage::local_directory`] materializes a checkout
+/// whose contents are taken over into the store the next time the document is accessed
+pub struct BlobStorage {
+    /// the filespider directory
+    pub root: String,
+    /// held while blobs and manifests change, so garbage collection never sees a blob
+    /// before the manifest referencing it is written
+    lock: Mutex<()>,
+}
+
+impl BlobStorage {
+    pub fn new(root: String) -> Self {
+        BlobStorage {
+            root,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// takes over changes made to the checkout of a document, the lock is only taken if there are any
+    async fn sync_checkout(&self, id: &Uuid) -> Result<Manifest> {
+        let checkout = get_document_checkout_directory(&self.root, id);
+        if !tokio::fs::try_exists(&checkout).await? {
+            return read_manifest(&self.root, id).await;
+        }
+
+        let _lock = self.lock.lock().await;
+        take_over_checkout(&self.root, id).await
+    }
+}
+
+/// blobs this young aren't collected, they might belong to a manifest that is about to be written
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// file name -> hex encoded SHA-256
+type Manifest = BTreeMap<String, String>;
+
+/// content of documents, named after their SHA-256
+fn get_blob_directory(root: &str) -> String {
+    format!("{}/.blobs", root)
+}
+
+/// file lists of documents
+fn get_manifest_directory(root: &str) -> String {
+    format!("{}/.manifests", root)
+}
+
+/// manifests of deleted documents, their blobs are kept until they are purged
+fn get_trash_directory(root: &str) -> String {
+    format!("{}/.trash", root)
+}
+
+/// copies of documents that are opened in an editor
+fn get_checkout_directory(root: &str) -> String {
+    format!("{}/.checkout", root)
+}
+
+fn get_blob_file(root: &str, hash: &str) -> String {
+    format!("{}/{}", get_blob_directory(root), hash)
+}
+
+fn get_manifest_file(root: &str, id: &Uuid) -> String {
+    format!("{}/{}.json", get_manifest_directory(root), id)
+}
+
+/// replaces the manifest once it is applied, see [`Storage::stage`]
+fn get_staged_manifest_file(root: &str, id: &Uuid) -> String {
+    format!("{}/{}.staged", get_manifest_directory(root), id)
+}
+
+fn get_trash_file(root: &str, id: &Uuid) -> String {
+    format!("{}/{}.json", get_trash_directory(root), id)
+}
+
+fn get_document_checkout_directory(root: &str, id: &Uuid) -> String {
+    format!("{}/{}", get_checkout_directory(root), id)
+}
+
+/// unique, so concurrent writes of the same content don't share a temporary file
+fn get_temp_file(file: &str) -> String {
+    format!("{}.{}.tmp", file, Uuid::new_v4())
+}
+
+/// the state of the files in the checkout when it was last taken over
+fn get_checkout_index_file(root: &str, id: &Uuid) -> String {
+    format!("{}/{}.json", get_checkout_directory(root), id)
+}
+
+async fn read_manifest(root: &str, id: &Uuid) -> Result<Manifest> {
+    match tokio::fs::read(get_manifest_file(root, id)).await {
+        Ok(m) => Ok(serde_json::from_slice(&m)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(eyre!("document does not exist")),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// the rename makes replacing the manifest atomic
+async fn write_manifest(root: &str, id: &Uuid, manifest: &Manifest) -> Result<()> {
+    tokio::fs::create_dir_all(get_manifest_directory(root)).await?;
+
+    let file = get_manifest_file(root, id);
+    let temp = get_temp_file(&file);
+    tokio::fs::write(&temp, serde_json::to_vec(manifest)?).await?;
+    tokio::fs::rename(temp, file).await?;
+    Ok(())
+}
+
+async fn store_blob(root: &str, contents: &[u8]) -> Result<String> {
+    let hash = format!("{:x}", Sha256::digest(contents));
+
+    let file = get_blob_file(root, &hash);
+    if !tokio::fs::try_exists(&file).await? {
+        tokio::fs::create_dir_all(get_blob_directory(root)).await?;
+
+        let temp = get_temp_file(&file);
+        tokio::fs::write(&temp, contents).await?;
+        tokio::fs::rename(temp, file).await?;
+    }
+
+    Ok(hash)
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct CheckoutFile {
+    size: u64,
+    modified: SystemTime,
+}
+
+/// file name -> size and modification time of the file in the checkout
+type CheckoutIndex = BTreeMap<String, CheckoutFile>;
+
+async fn read_checkout_index(root: &str, id: &Uuid) -> Result<CheckoutIndex> {
+    match tokio::fs::read(get_checkout_index_file(root, id)).await {
+        Ok(i) => Ok(serde_json::from_slice(&i)?),
+        // checkouts from before the index existed are taken over completely once
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CheckoutIndex::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write_checkout_index(root: &str, id: &Uuid, index: &CheckoutIndex) -> Result<()> {
+    let file = get_checkout_index_file(root, id);
+    let temp = get_temp_file(&file);
+    tokio::fs::write(&temp, serde_json::to_vec(index)?).await?;
+    tokio::fs::rename(temp, file).await?;
+    Ok(())
+}
+
+async fn stat_checkout(checkout: &str) -> Result<CheckoutIndex> {
+    let mut index = CheckoutIndex::new();
+    let mut entries = tokio::fs::read_dir(checkout).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            index.insert(
+                entry.file_name().to_string_lossy().to_string(),
+                CheckoutFile {
+                    size: metadata.len(),
+                    modified: metadata.modified()?,
+                },
+            );
+        }
+    }
+    Ok(index)
+}
+
+async fn remove_checkout(root: &str, id: &Uuid) -> Result<()> {
+    let checkout = get_document_checkout_directory(root, id);
+    if tokio::fs::try_exists(&checkout).await? {
+        tokio::fs::remove_dir_all(checkout).await?;
+    }
+
+    let index = get_checkout_index_file(root, id);
+    if tokio::fs::try_exists(&index).await? {
+        tokio::fs::remove_file(index).await?;
+    }
+
+    Ok(())
+}
+
+/// takes over changes made to the checkout of a document, only files whose size or modification time
+/// changed since the last time are read again, has to be called with the lock held
+async fn take_over_checkout(root: &str, id: &Uuid) -> Result<Manifest> {
+    let manifest = read_manifest(root, id).await?;
+
+    let checkout = get_document_checkout_directory(root, id);
+    if !tokio::fs::try_exists(&checkout).await? {
+        return Ok(manifest);
+    }
+
+    let index = read_checkout_index(root, id).await?;
+    let files = stat_checkout(&checkout).await?;
+    if files == index && files.keys().eq(manifest.keys()) {
+        return Ok(manifest);
+    }
+
+    let mut synced = Manifest::new();
+    for (name, file) in files.iter() {
+        let hash = match (index.get(name), manifest.get(name)) {
+            (Some(indexed), Some(hash)) if indexed == file => hash.clone(),
+            _ => {
+                let contents = tokio::fs::read(format!("{}/{}", checkout, name)).await?;
+                store_blob(root, &contents).await?
+            }
+        };
+        synced.insert(name.clone(), hash);
+    }
+
+    if synced != manifest {
+        write_manifest(root, id, &synced).await?;
+    }
+    write_checkout_index(root, id, &files).await?;
+
+    Ok(synced)
+}
+
+/// removes blobs that aren't referenced by any manifest, including the ones in the trash
+/// and the staged ones, has to be called with the lock held
+async fn collect_garbage(root: &str) -> Result<()> {
+    let mut referenced = HashSet::new();
+
+    for directory in [get_manifest_directory(root), get_trash_directory(root)] {
+        if !tokio::fs::try_exists(&directory).await? {
+            continue;
+        }
+
+        let mut manifests = tokio::fs::read_dir(directory).await?;
+        while let Some(entry) = manifests.next_entry().await? {
+            if entry
+                .path()
+                .extension()
+                .is_some_and(|e| e == "json" || e == "staged")
+            {
+                let manifest: Manifest =
+                    serde_json::from_slice(&tokio::fs::read(entry.path()).await?)?;
+                referenced.extend(manifest.into_values());
+            }
+        }
+    }
+
+    if !tokio::fs::try_exists(get_blob_directory(root)).await? {
+        return Ok(());
+    }
+
+    let mut blobs = tokio::fs::read_dir(get_blob_directory(root)).await?;
+    while let Some(entry) = blobs.next_entry().await? {
+        // blobs that are being written or were just stored by another process
+        let age = entry
+            .metadata()
+            .await?
+            .modified()?
+            .elapsed()
+            .unwrap_or_default();
+        if age < GC_GRACE_PERIOD {
+            continue;
+        }
+
+        let temp = entry.path().extension().is_some_and(|e| e == "tmp");
+        if temp || !referenced.contains(entry.file_name().to_string_lossy().as_ref()) {
+            tokio::fs::remove_file(entry.path()).await?;
+            info!("removed unreferenced blob {:?}", entry.file_name());
+        }
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for BlobStorage {
+    async fn list_documents(&self) -> Result<HashSet<Uuid>> {
+        let mut documents = HashSet::new();
+
+        if !tokio::fs::try_exists(get_manifest_directory(&self.root)).await? {
+            return Ok(documents);
+        }
+
+        let mut entries = tokio::fs::read_dir(get_manifest_directory(&self.root)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                if let Some(Ok(id)) = path
+                    .file_stem()
+                    .map(|s| Uuid::from_str(&s.to_string_lossy()))
+                {
+                    documents.insert(id);
+                }
+            }
+        }
+
+        Ok(documents)
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool> {
+        Ok(tokio::fs::try_exists(get_manifest_file(&self.root, id)).await?)
+    }
+
+    async fn list(&self, id: &Uuid) -> Result<Vec<String>> {
+        Ok(self.sync_checkout(id).await?.into_keys().collect())
+    }
+
+    async fn read(&self, id: &Uuid, name: &str) -> Result<Vec<u8>> {
+        let manifest = self.sync_checkout(id).await?;
+        let hash = manifest
+            .get(name)
+            .ok_or(eyre!("document has no file {}", name))?;
+        Ok(tokio::fs::read(get_blob_file(&self.root, hash)).await?)
+    }
+
+    async fn write(&self, id: &Uuid, name: &str, contents: &[u8]) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        let mut manifest = take_over_checkout(&self.root, id).await?;
+        manifest.insert(name.to_string(), store_blob(&self.root, contents).await?);
+        write_manifest(&self.root, id, &manifest).await?;
+
+        // otherwise the stale checkout would be taken over again
+        let checkout = get_document_checkout_directory(&self.root, id);
+        if tokio::fs::try_exists(&checkout).await? {
+            tokio::fs::write(format!("{}/{}", checkout, name), contents).await?;
+            write_checkout_index(&self.root, id, &stat_checkout(&checkout).await?).await?;
+        }
+
+        Ok(())
+    }
+
+    /// the manifest is written last, so the document doesn't exist until all blobs are stored
+    async fn insert(&self, id: &Uuid, files: Vec<(String, Vec<u8>)>) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        if self.exists(id).await? {
+            return Err(eyre!("document already exists"));
+        }
+
+        let mut manifest = Manifest::new();
+        for (name, contents) in files {
+            manifest.insert(name, store_blob(&self.root, &contents).await?);
+        }
+        write_manifest(&self.root, id, &manifest).await
+    }
+
+    /// the blobs are stored right away, only the manifest waits
+    async fn stage(&self, id: &Uuid, files: Vec<(String, Vec<u8>)>) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        let mut manifest = Manifest::new();
+        for (name, contents) in files {
+            manifest.insert(name, store_blob(&self.root, &contents).await?);
+        }
+
+        tokio::fs::create_dir_all(get_manifest_directory(&self.root)).await?;
+        let file = get_staged_manifest_file(&self.root, id);
+        let temp = get_temp_file(&file);
+        tokio::fs::write(&temp, serde_json::to_vec(&manifest)?).await?;
+        tokio::fs::rename(temp, file).await?;
+        Ok(())
+    }
+
+    /// the checkout is rewritten, otherwise its old files would be taken over again
+    async fn apply_staged(&self, id: &Uuid) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        let staged = get_staged_manifest_file(&self.root, id);
+        let manifest: Manifest = serde_json::from_slice(&tokio::fs::read(&staged).await?)?;
+        tokio::fs::rename(staged, get_manifest_file(&self.root, id)).await?;
+
+        let checkout = get_document_checkout_directory(&self.root, id);
+        if tokio::fs::try_exists(&checkout).await? {
+            for name in stat_checkout(&checkout).await?.into_keys() {
+                if !manifest.contains_key(&name) {
+                    tokio::fs::remove_file(format!("{}/{}", checkout, name)).await?;
+                }
+            }
+            for (name, hash) in manifest.iter() {
+                tokio::fs::copy(
+                    get_blob_file(&self.root, hash),
+                    format!("{}/{}", checkout, name),
+                )
+                .await?;
+            }
+            write_checkout_index(&self.root, id, &stat_checkout(&checkout).await?).await?;
+        }
+
+        Ok(())
+    }
+
+    /// the blobs are removed by the next garbage collection
+    async fn discard_staged(&self, id: &Uuid) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        let staged = get_staged_manifest_file(&self.root, id);
+        if tokio::fs::try_exists(&staged).await? {
+            tokio::fs::remove_file(staged).await?;
+        }
+        Ok(())
+    }
+
+    /// the blobs are removed by the next garbage collection
+    async fn delete(&self, id: &Uuid) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        tokio::fs::remove_file(get_manifest_file(&self.root, id)).await?;
+        remove_checkout(&self.root, id).await
+    }
+
+    async fn trash(&self, id: &Uuid) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        // edits in the checkout would be lost otherwise
+        take_over_checkout(&self.root, id).await?;
+
+        tokio::fs::create_dir_all(get_trash_directory(&self.root)).await?;
+        tokio::fs::rename(
+            get_manifest_file(&self.root, id),
+            get_trash_file(&self.root, id),
+        )
+        .await?;
+
+        remove_checkout(&self.root, id).await
+    }
+
+    async fn restore(&self, id: &Uuid) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        tokio::fs::rename(
+            get_trash_file(&self.root, id),
+            get_manifest_file(&self.root, id),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn purge(&self, id: &Uuid) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        tokio::fs::remove_file(get_trash_file(&self.root, id)).await?;
+        collect_garbage(&self.root).await
+    }
+
+    /// staged manifests are left over from imports that were interrupted
+    async fn collect_garbage(&self) -> Result<()> {
+        let _lock = self.lock.lock().await;
+
+        let manifests = get_manifest_directory(&self.root);
+        if tokio::fs::try_exists(&manifests).await? {
+            let mut entries = tokio::fs::read_dir(&manifests).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.path().extension().is_some_and(|e| e == "staged") {
+                    tokio::fs::remove_file(entry.path()).await?;
+                }
+            }
+        }
+
+        collect_garbage(&self.root).await
+    }
+
+    /// based on the contents, so touching a file doesn't invalidate the render
+    async fn hash(&self, id: &Uuid) -> Result<Hash> {
+        let mut hasher = fxhash::FxHasher::default();
+        for (name, hash) in self.sync_checkout(id).await? {
+            hasher.write(name.as_bytes());
+            hasher.write(hash.as_bytes());
+        }
+        Ok(hasher.finish())
+    }
+
+    async fn local_directory(&self, id: &Uuid) -> Result<String> {
+        let _lock = self.lock.lock().await;
+
+        let checkout = get_document_checkout_directory(&self.root, id);
+        if tokio::fs::try_exists(&checkout).await? {
+            return Ok(checkout);
+        }
+
+        let manifest = read_manifest(&self.root, id).await?;
+
+        let temp = get_temp_file(&checkout);
+        tokio::fs::create_dir_all(&temp).await?;
+        for (name, hash) in manifest {
+            tokio::fs::copy(
+                get_blob_file(&self.root, &hash),
+                format!("{}/{}", temp, name),
+            )
+            .await?;
+        }
+        tokio::fs::rename(temp, &checkout).await?;
+        write_checkout_index(&self.root, id, &stat_checkout(&checkout).await?).await?;
+
+        Ok(checkout)
+    }
+
+    /// the manifest is rewritten whenever a file changes, so its modification time is the document's
+    async fn stat(&self, id: &Uuid) -> Result<FileStat> {
+        let mut size = 0;
+        for hash in self.sync_checkout(id).await?.into_values() {
+            size += tokio::fs::metadata(get_blob_file(&self.root, &hash))
+                .await?
+                .len();
+        }
+
+        Ok(FileStat {
+            size,
+            modified: tokio::fs::metadata(get_manifest_file(&self.root, id))
+                .await?
+                .modified()?,
+        })
+    }
+}
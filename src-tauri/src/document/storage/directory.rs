@@ -0,0 +1,249 @@
+use std::collections::HashSet;
+use std::hash::Hasher;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use async_recursion::async_recursion;
+use async_trait::async_trait;
+use eyre::Result;
+use log::error;
+use uuid::Uuid;
+
+use crate::document::render::Hash;
+
+use super::{FileStat, Storage};
+
+/// one directory per document in the filespider directory, named after the document id
+pub struct DirectoryStorage {
+    /// the filespider directory
+    pub root: String,
+}
+
+impl DirectoryStorage {
+    fn get_document_directory(&self, id: &Uuid) -> String {
+        format!("{}/{}", self.root, id)
+    }
+
+    fn get_document_trash_directory(&self, id: &Uuid) -> String {
+        format!("{}/.trash/{}", self.root, id)
+    }
+
+    /// new documents are written here before being moved into place
+    fn get_document_staging_directory(&self, id: &Uuid) -> String {
+        format!("{}/.staging/{}", self.root, id)
+    }
+}
+
+#[async_trait]
+impl Storage for DirectoryStorage {
+    async fn list_documents(&self) -> Result<HashSet<Uuid>> {
+        let mut documents = HashSet::new();
+
+        let mut entries = tokio::fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            // skips .cache, .staging and anything else that doesn't belong to a document
+            if let Ok(id) = Uuid::from_str(&entry.file_name().to_string_lossy()) {
+                documents.insert(id);
+            }
+        }
+
+        Ok(documents)
+    }
+
+    async fn exists(&self, id: &Uuid) -> Result<bool> {
+        Ok(tokio::fs::try_exists(self.get_document_directory(id)).await?)
+    }
+
+    async fn list(&self, id: &Uuid) -> Result<Vec<String>> {
+        let mut names = vec![];
+
+        let mut entries = tokio::fs::read_dir(self.get_document_directory(id)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    async fn read(&self, id: &Uuid, name: &str) -> Result<Vec<u8>> {
+        Ok(tokio::fs::read(format!("{}/{}", self.get_document_directory(id), name)).await?)
+    }
+
+    async fn write(&self, id: &Uuid, name: &str, contents: &[u8]) -> Result<()> {
+        tokio::fs::write(
+            format!("{}/{}", self.get_document_directory(id), name),
+            contents,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// writes the files into a staging directory and moves it into place
+    async fn insert(&self, id: &Uuid, files: Vec<(String, Vec<u8>)>) -> Result<()> {
+        let staging = self.get_document_staging_directory(id);
+        tokio::fs::create_dir_all(&staging).await?;
+
+        let res: Result<()> = try {
+            for (name, contents) in files {
+                tokio::fs::write(format!("{}/{}", staging, name), contents).await?;
+            }
+            // staging lives in the filespider directory, so this is an atomic rename
+            tokio::fs::rename(&staging, self.get_document_directory(id)).await?;
+        };
+
+        if res.is_err() {
+            if let Err(e) = tokio::fs::remove_dir_all(&staging).await {
+                error!("failed to remove staging directory {}: {:?}", staging, e);
+            }
+        }
+        res
+    }
+
+    /// the files are written to the same staging directory as the ones of [`Storage::insert`]
+    async fn stage(&self, id: &Uuid, files: Vec<(String, Vec<u8>)>) -> Result<()> {
+        self.discard_staged(id).await?;
+
+        let staging = self.get_document_staging_directory(id);
+        tokio::fs::create_dir_all(&staging).await?;
+
+        let res: Result<()> = try {
+            for (name, contents) in files {
+                tokio::fs::write(format!("{}/{}", staging, name), contents).await?;
+            }
+        };
+
+        if res.is_err() {
+            self.discard_staged(id).await?;
+        }
+        res
+    }
+
+    /// a directory can't be renamed over one that isn't empty, so the old one is moved away first
+    async fn apply_staged(&self, id: &Uuid) -> Result<()> {
+        let directory = self.get_document_directory(id);
+        let replaced = format!("{}.replaced", self.get_document_staging_directory(id));
+
+        let exists = tokio::fs::try_exists(&directory).await?;
+        if exists {
+            tokio::fs::rename(&directory, &replaced).await?;
+        }
+        if let Err(e) = tokio::fs::rename(self.get_document_staging_directory(id), &directory).await
+        {
+            if exists {
+                tokio::fs::rename(&replaced, &directory).await?;
+            }
+            return Err(e.into());
+        }
+        if exists {
+            tokio::fs::remove_dir_all(replaced).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn discard_staged(&self, id: &Uuid) -> Result<()> {
+        let staging = self.get_document_staging_directory(id);
+        if tokio::fs::try_exists(&staging).await? {
+            tokio::fs::remove_dir_all(staging).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: &Uuid) -> Result<()> {
+        tokio::fs::remove_dir_all(self.get_document_directory(id)).await?;
+        Ok(())
+    }
+
+    async fn trash(&self, id: &Uuid) -> Result<()> {
+        tokio::fs::create_dir_all(format!("{}/.trash", self.root)).await?;
+        tokio::fs::rename(
+            self.get_document_directory(id),
+            self.get_document_trash_directory(id),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn restore(&self, id: &Uuid) -> Result<()> {
+        tokio::fs::rename(
+            self.get_document_trash_directory(id),
+            self.get_document_directory(id),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn purge(&self, id: &Uuid) -> Result<()> {
+        tokio::fs::remove_dir_all(self.get_document_trash_directory(id)).await?;
+        Ok(())
+    }
+
+    /// documents that were being inserted when FileSpider stopped
+    async fn collect_garbage(&self) -> Result<()> {
+        let staging = format!("{}/.staging", self.root);
+        if tokio::fs::try_exists(&staging).await? {
+            tokio::fs::remove_dir_all(staging).await?;
+        }
+        Ok(())
+    }
+
+    /// only looks at modification times, so it doesn't have to read every file
+    async fn hash(&self, id: &Uuid) -> Result<Hash> {
+        let mut hasher = fxhash::FxHasher::default();
+        hash_file(&mut hasher, PathBuf::from(self.get_document_directory(id))).await?;
+        Ok(hasher.finish())
+    }
+
+    async fn local_directory(&self, id: &Uuid) -> Result<String> {
+        Ok(self.get_document_directory(id))
+    }
+
+    async fn stat(&self, id: &Uuid) -> Result<FileStat> {
+        let mut stat = FileStat {
+            size: 0,
+            modified: UNIX_EPOCH,
+        };
+        stat_file(&mut stat, PathBuf::from(self.get_document_directory(id))).await?;
+        Ok(stat)
+    }
+}
+
+#[async_recursion]
+async fn hash_file(hasher: &mut fxhash::FxHasher, path: PathBuf) -> Result<()> {
+    let meta = tokio::fs::metadata(&path).await?;
+
+    if meta.is_dir() {
+        let mut entries = tokio::fs::read_dir(&path).await?;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            hash_file(hasher, entry.path()).await?;
+        }
+    } else {
+        hasher.write_u128(meta.modified()?.duration_since(UNIX_EPOCH)?.as_millis());
+    }
+
+    Ok(())
+}
+
+#[async_recursion]
+async fn stat_file(stat: &mut FileStat, path: PathBuf) -> Result<()> {
+    let meta = tokio::fs::metadata(&path).await?;
+
+    if meta.is_dir() {
+        let mut entries = tokio::fs::read_dir(&path).await?;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            stat_file(stat, entry.path()).await?;
+        }
+    } else {
+        stat.size += meta.len();
+        stat.modified = stat.modified.max(meta.modified()?);
+    }
+
+    Ok(())
+}
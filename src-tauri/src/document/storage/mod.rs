@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use log::info;
+use uuid::Uuid;
+
+use crate::directories::get_filespider_directory;
+use crate::types::StorageBackend;
+
+use super::render::Hash;
+
+pub mod blob;
+pub mod directory;
+
+/// where the files of documents are kept, files are addressed by document id and file name,
+/// the main file of a document is named like [`super::get_document_basename`]
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn list_documents(&self) -> Result<HashSet<Uuid>>;
+
+    async fn exists(&self, id: &Uuid) -> Result<bool>;
+
+    /// names of the files of a document
+    async fn list(&self, id: &Uuid) -> Result<Vec<String>>;
+
+    async fn read(&self, id: &Uuid, name: &str) -> Result<Vec<u8>>;
+
+    /// creates or replaces a file of an existing document
+    async fn write(&self, id: &Uuid, name: &str, contents: &[u8]) -> Result<()>;
+
+    /// stores the files of a new document, either all of them are stored or none
+    async fn insert(&self, id: &Uuid, files: Vec<(String, Vec<u8>)>) -> Result<()>;
+
+    /// writes the files that are to replace all files of a document, which is created if it doesn't exist.
+    /// nothing changes until [`Storage::apply_staged`], so it can be called before the DB change is committed
+    async fn stage(&self, id: &Uuid, files: Vec<(String, Vec<u8>)>) -> Result<()>;
+
+    /// moves the staged files into place, files of the document that weren't staged are removed
+    async fn apply_staged(&self, id: &Uuid) -> Result<()>;
+
+    /// throws away the staged files, e.g. because the DB change they belong to failed
+    async fn discard_staged(&self, id: &Uuid) -> Result<()>;
+
+    async fn delete(&self, id: &Uuid) -> Result<()>;
+
+    /// removes the document like [`Storage::delete`], but keeps the files until it is purged
+    async fn trash(&self, id: &Uuid) -> Result<()>;
+
+    /// brings back a document from the trash
+    async fn restore(&self, id: &Uuid) -> Result<()>;
+
+    /// deletes a document in the trash for good
+    async fn purge(&self, id: &Uuid) -> Result<()>;
+
+    /// removes files that no document needs anymore, like leftovers of interrupted writes
+    async fn collect_garbage(&self) -> Result<()>;
+
+    /// changes whenever a file of the document changes, used as the key of the render cache
+    async fn hash(&self, id: &Uuid) -> Result<Hash>;
+
+    /// a directory containing the files of the document that external editors can change in place
+    async fn local_directory(&self, id: &Uuid) -> Result<String>;
+
+    /// total size and last modification of the files of a document
+    async fn stat(&self, id: &Uuid) -> Result<FileStat>;
+}
+
+pub struct FileStat {
+    /// in bytes
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// names that some backends would resolve outside of the document, e.g. in files from a sync peer
+pub fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && !name.contains("..") && !name.contains(['/', '\\'])
+}
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+/// opens the documents of the filespider directory `root`, most code should use [`get`] instead
+pub fn open(backend: &StorageBackend, root: String) -> Box<dyn Storage> {
+    match backend {
+        StorageBackend::Directory => Box::new(directory::DirectoryStorage { root }),
+        StorageBackend::Blob => Box::new(blob::BlobStorage::new(root)),
+    }
+}
+
+/// selects the backend for the rest of the process, has to be called before any document is accessed
+pub fn init(backend: &StorageBackend) -> Result<()> {
+    STORAGE
+        .set(open(backend, get_filespider_directory()?))
+        .map_err(|_| eyre!("storage is already initialized"))
+}
+
+/// the backend selected with [`init`], the directory backend if none was selected
+pub fn get() -> Result<&'static dyn Storage> {
+    let root = get_filespider_directory()?;
+    Ok(STORAGE
+        .get_or_init(|| open(&StorageBackend::Directory, root))
+        .as_ref())
+}
+
+/// writes all files of a document into `dest`, e.g. for renderers that need them on disk
+pub async fn export(storage: &dyn Storage, id: &Uuid, dest: &Path) -> Result<()> {
+    for name in storage.list(id).await? {
+        tokio::fs::write(dest.join(&name), storage.read(id, &name).await?).await?;
+    }
+    Ok(())
+}
+
+/// moves all documents from one backend to another, FileSpider must not be running
+pub async fn transfer(from: &StorageBackend, to: &StorageBackend) -> Result<()> {
+    if from == to {
+        return Ok(());
+    }
+
+    let from = open(from, get_filespider_directory()?);
+    let to = open(to, get_filespider_directory()?);
+
+    for id in from.list_documents().await? {
+        let mut files = vec![];
+        for name in from.list(&id).await? {
+            let contents = from.read(&id, &name).await?;
+            files.push((name, contents));
+        }
+
+        to.insert(&id, files).await?;
+        from.delete(&id).await?;
+        info!("transferred document {}", id);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use eyre::Result;
+use log::warn;
+use sqlx::{query, SqlitePool};
+use uuid::Uuid;
+
+use crate::types::{Meta, TagSuggestion};
+
+use super::auto_tag::content_text;
+use super::{get_document_basename, get_meta, meta_from_row, storage, META_COLUMNS};
+
+/// the text of this many of the most recently accessed tagged documents is compared,
+/// so suggestions don't have to read the whole library
+const SAMPLE_SIZE: u32 = 200;
+
+/// shorter words are mostly stop words or markup
+const MIN_TERM_LENGTH: usize = 3;
+
+/// terms of the sampled documents, so repeated suggestions only read the documents that changed
+static TERMS: Mutex<BTreeMap<Uuid, CachedTerms>> = Mutex::new(BTreeMap::new());
+
+struct CachedTerms {
+    title: String,
+    size: u64,
+    modified: SystemTime,
+    terms: Arc<HashSet<String>>,
+}
+
+/// tags the document doesn't have yet, best first
+pub async fn suggest(pool: &SqlitePool, id: Uuid, count: u32) -> Result<Vec<TagSuggestion>> {
+    let meta = get_meta(pool, id).await?;
+
+    let co_occurrence = co_occurrence(pool, id, &meta.tags).await?;
+    let similarity = similarity(pool, id, &meta.title, &meta.extension).await?;
+
+    let mut suggestions: Vec<TagSuggestion> = co_occurrence
+        .keys()
+        .chain(similarity.keys())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .filter(|tag| !meta.tags.contains(tag))
+        .map(|tag| TagSuggestion {
+            tag: tag.clone(),
+            score: (co_occurrence.get(tag).unwrap_or(&0.0) + similarity.get(tag).unwrap_or(&0.0))
+                / 2.0,
+        })
+        .filter(|s| s.score > 0.0)
+        .collect();
+
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.tag.cmp(&b.tag)));
+    suggestions.truncate(count as usize);
+
+    Ok(suggestions)
+}
+
+/// for every other tag the share of documents with one of `tags` that also have it, averaged over `tags`
+async fn co_occurrence(
+    pool: &SqlitePool,
+    id: Uuid,
+    tags: &[String],
+) -> Result<HashMap<String, f64>> {
+    let mut scores = HashMap::new();
+    if tags.is_empty() {
+        return Ok(scores);
+    }
+
+    let totals: HashMap<String, i64> = query!(
+        r#"select tag, count(document) as "count!: i64" from Tag where document != ? and tag in (select tag from Tag where document = ?) group by tag"#,
+        id,
+        id
+    )
+    .map(|x| (x.tag, x.count))
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+
+    for x in query!(
+        r#"select own.tag as tag, other.tag as other, count(own.document) as "count!: i64"
+        from Tag as own join Tag as other on other.document = own.document and other.tag != own.tag
+        where own.document != ? and own.tag in (select tag from Tag where document = ?)
+        group by own.tag, other.tag"#,
+        id,
+        id
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        let total = totals.get(&x.tag).copied().unwrap_or(1) as f64;
+        *scores.entry(x.other).or_insert(0.0) += x.count as f64 / total / tags.len() as f64;
+    }
+
+    Ok(scores)
+}
+
+/// for every tag the idf weighted share of the terms of the document that appear in documents with the tag
+async fn similarity(
+    pool: &SqlitePool,
+    id: Uuid,
+    title: &str,
+    extension: &Option<String>,
+) -> Result<HashMap<String, f64>> {
+    let content = storage::get()?
+        .read(&id, &get_document_basename(&id, extension))
+        .await?;
+    let terms = split_terms(&format!("{} {}", title, content_text(&content)));
+
+    let sample = sqlx::query(&format!(
+        "select Document.id as id {META_COLUMNS} from Document where Document.id != ? and exists (select tag from Tag where Tag.document = Document.id) order by Document.accessed desc limit ?"
+    ))
+    .bind(id)
+    .bind(SAMPLE_SIZE)
+    .fetch_all(pool)
+    .await?;
+
+    // number of sampled documents with the term, overall and by tag
+    let mut document_frequency: HashMap<&str, u32> = HashMap::new();
+    let mut tag_frequency: HashMap<String, HashMap<&str, u32>> = HashMap::new();
+    let mut tag_documents: HashMap<String, u32> = HashMap::new();
+
+    let mut sampled = HashSet::new();
+
+    for row in sample.iter() {
+        let meta = meta_from_row(row)?;
+        // one broken document shouldn't prevent suggestions
+        let document_terms = match document_terms(&meta).await {
+            Ok(terms) => terms,
+            Err(e) => {
+                warn!("skipped document {} for tag suggestions: {:?}", meta.id, e);
+                continue;
+            }
+        };
+        sampled.insert(meta.id);
+
+        let shared: Vec<&str> = terms
+            .iter()
+            .filter(|t| document_terms.contains(*t))
+            .map(|t| t.as_str())
+            .collect();
+        for term in shared.iter() {
+            *document_frequency.entry(term).or_insert(0) += 1;
+        }
+        for tag in meta.tags {
+            *tag_documents.entry(tag.clone()).or_insert(0) += 1;
+            let frequency = tag_frequency.entry(tag).or_default();
+            for term in shared.iter() {
+                *frequency.entry(term).or_insert(0) += 1;
+            }
+        }
+    }
+
+    TERMS.lock().unwrap().retain(|id, _| sampled.contains(id));
+
+    // terms that no sampled document has can't tell the tags apart
+    let idf = |term: &str| match document_frequency.get(term) {
+        Some(&df) => (sampled.len() as f64 / df as f64).ln_1p(),
+        None => 0.0,
+    };
+    let total_weight: f64 = terms.iter().map(|t| idf(t)).sum();
+    if total_weight == 0.0 {
+        return Ok(HashMap::new());
+    }
+
+    Ok(tag_frequency
+        .into_iter()
+        .map(|(tag, frequency)| {
+            let documents = tag_documents[&tag] as f64;
+            let score = frequency
+                .into_iter()
+                .map(|(term, tf)| idf(term) * tf as f64 / documents)
+                .sum::<f64>()
+                / total_weight;
+            (tag, score)
+        })
+        .collect())
+}
+
+/// the terms of the title and main file, cached until either changes
+async fn document_terms(meta: &Meta) -> Result<Arc<HashSet<String>>> {
+    let stat = storage::get()?.stat(&meta.id).await?;
+    if let Some(cached) = TERMS.lock().unwrap().get(&meta.id) {
+        if cached.title == meta.title
+            && cached.size == stat.size
+            && cached.modified == stat.modified
+        {
+            return Ok(cached.terms.clone());
+        }
+    }
+
+    let content = storage::get()?
+        .read(&meta.id, &get_document_basename(&meta.id, &meta.extension))
+        .await?;
+    let terms = Arc::new(split_terms(&format!(
+        "{} {}",
+        meta.title,
+        content_text(&content)
+    )));
+
+    TERMS.lock().unwrap().insert(
+        meta.id,
+        CachedTerms {
+            title: meta.title.clone(),
+            size: stat.size,
+            modified: stat.modified,
+            terms: terms.clone(),
+        },
+    );
+    Ok(terms)
+}
+
+/// lowercase words without duplicates
+fn split_terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.chars().count() >= MIN_TERM_LENGTH)
+        .map(|t| t.to_lowercase())
+        .collect()
+}
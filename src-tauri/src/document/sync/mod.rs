@@ -0,0 +1,483 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use eyre::{eyre, Result, WrapErr};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, SqlitePool};
+use uuid::Uuid;
+
+use crate::db;
+use crate::settings::read_storage_backend;
+use crate::types::{DocType, JournalOperation, Meta, SyncReport};
+
+use super::storage::{self, Storage};
+use super::{as_base64, from_base64, get_document_basename, new_document_id};
+use super::{journal, tags};
+
+pub mod remote;
+
+#[cfg(test)]
+mod tests;
+
+/// hash of the metadata, tags and files of a document, only comparable to versions of the same replica
+pub type Version = String;
+
+/// everything that is synced of a document
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub meta: Meta,
+    pub files: Vec<SnapshotFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotFile {
+    pub name: String,
+    #[serde(serialize_with = "as_base64", deserialize_with = "from_base64")]
+    pub contents: Vec<u8>,
+}
+
+/// one side of a sync, either a filespider directory or a peer connected with [`remote`]
+#[async_trait]
+pub trait Replica: Send + Sync {
+    async fn id(&self) -> Result<Uuid>;
+
+    /// the current version of every document
+    async fn versions(&self) -> Result<HashMap<Uuid, Version>>;
+
+    /// the versions recorded by the last [`Replica::record_synced`] for `peer`
+    async fn synced_versions(&self, peer: Uuid) -> Result<HashMap<Uuid, Version>>;
+
+    async fn export(&self, id: Uuid) -> Result<Snapshot>;
+
+    /// creates the document or overwrites its metadata, tags and files, returns its new version
+    async fn import(&self, snapshot: Snapshot) -> Result<Version>;
+
+    async fn remove(&self, id: Uuid) -> Result<()>;
+
+    /// remembers `versions` as the state both sides agreed on
+    async fn record_synced(&self, peer: Uuid, versions: &HashMap<Uuid, Version>) -> Result<()>;
+}
+
+/// the DB and storage backend of a filespider directory
+pub struct LocalReplica {
+    pool: SqlitePool,
+    storage: Box<dyn Storage>,
+}
+
+impl LocalReplica {
+    /// opens the filespider directory `root` with the storage backend from its settings
+    pub async fn open(root: &str) -> Result<Self> {
+        Ok(Self {
+            pool: db::open(&format!("{}/filespider.sqlite", root)).await?,
+            storage: storage::open(&read_storage_backend(root).await?, root.to_string()),
+        })
+    }
+
+    async fn get_meta(&self, id: Uuid) -> Result<Meta> {
+        let doc = query!(
+            "select title, type, added, file_extension, accessed from Document where id = ?",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(eyre!("document does not exist"))?;
+
+        let tags = query!("select tag from Tag where document = ? order by tag", id)
+            .map(|x| x.tag)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(Meta {
+            title: doc.title,
+            doc_type: DocType::from_str(&doc.r#type)?,
+            tags,
+            created: doc.added,
+            accessed: doc.accessed,
+            id,
+            extension: doc.file_extension,
+        })
+    }
+
+    /// the access time is left out, opening a document on both sides shouldn't be a conflict
+    async fn version(
+        &self,
+        id: Uuid,
+        title: &str,
+        doc_type: &str,
+        extension: Option<&str>,
+        tags: &[String],
+    ) -> Result<Version> {
+        let mut hasher = fxhash::FxHasher::default();
+        hasher.write(title.as_bytes());
+        hasher.write(doc_type.as_bytes());
+        hasher.write(extension.unwrap_or_default().as_bytes());
+        for tag in tags {
+            hasher.write(tag.as_bytes());
+        }
+        hasher.write_u64(
+            self.storage
+                .hash(&id)
+                .await
+                .wrap_err(format!("failed to hash files of document {}", id))?,
+        );
+
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+}
+
+#[async_trait]
+impl Replica for LocalReplica {
+    async fn id(&self) -> Result<Uuid> {
+        if let Some(id) = query!(r#"select id as "id!: Uuid" from Replica"#)
+            .map(|x| x.id)
+            .fetch_optional(&self.pool)
+            .await?
+        {
+            return Ok(id);
+        }
+
+        let id = Uuid::new_v4();
+        query!("insert into Replica (id) values (?)", id)
+            .execute(&self.pool)
+            .await?;
+        Ok(id)
+    }
+
+    async fn versions(&self) -> Result<HashMap<Uuid, Version>> {
+        let mut tags: HashMap<Uuid, Vec<String>> = HashMap::new();
+        for tag in query!(r#"select document as "document!: Uuid", tag from Tag order by tag"#)
+            .fetch_all(&self.pool)
+            .await?
+        {
+            tags.entry(tag.document).or_default().push(tag.tag);
+        }
+
+        let documents =
+            query!(r#"select id as "id!: Uuid", title, type, file_extension from Document"#)
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut versions = HashMap::new();
+        for doc in documents {
+            let version = self
+                .version(
+                    doc.id,
+                    &doc.title,
+                    &doc.r#type,
+                    doc.file_extension.as_deref(),
+                    tags.get(&doc.id).map_or(&[], |t| t.as_slice()),
+                )
+                .await?;
+            versions.insert(doc.id, version);
+        }
+
+        Ok(versions)
+    }
+
+    async fn synced_versions(&self, peer: Uuid) -> Result<HashMap<Uuid, Version>> {
+        Ok(query!(
+            r#"select document as "document!: Uuid", version from SyncLog where peer = ?"#,
+            peer
+        )
+        .map(|x| (x.document, x.version))
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .collect())
+    }
+
+    async fn export(&self, id: Uuid) -> Result<Snapshot> {
+        let meta = self.get_meta(id).await?;
+
+        let mut files = vec![];
+        for name in self.storage.list(&id).await? {
+            let contents = self.storage.read(&id, &name).await?;
+            files.push(SnapshotFile { name, contents });
+        }
+
+        Ok(Snapshot { meta, files })
+    }
+
+    /// files that only exist on this side are removed, tags are stored in their canonical form.
+    /// the files are staged until the DB change is committed, so a failed import changes neither
+    async fn import(&self, snapshot: Snapshot) -> Result<Version> {
+        let mut meta = snapshot.meta;
+        let doc_type = meta.doc_type.to_string();
+        let added = meta.created.and_utc();
+        let accessed = meta.accessed.and_utc();
+
+        if let Some(file) = snapshot
+            .files
+            .iter()
+            .find(|f| !storage::is_valid_file_name(&f.name))
+        {
+            return Err(eyre!(
+                "invalid file name {:?} in document {}",
+                file.name,
+                meta.id
+            ));
+        }
+
+        self.storage
+            .stage(
+                &meta.id,
+                snapshot
+                    .files
+                    .into_iter()
+                    .map(|f| (f.name, f.contents))
+                    .collect(),
+            )
+            .await?;
+
+        let res: Result<()> = try {
+            let mut tx = self.pool.begin().await?;
+
+            meta.tags = tags::canonical_all(&mut tx, meta.tags.clone()).await?;
+            meta.tags.sort();
+
+            query!(
+                "insert into Document (id, title, type, added, file_extension, accessed) values (?, ?, ?, ?, ?, ?)
+                on conflict(id) do update set title = excluded.title, type = excluded.type, added = excluded.added,
+                file_extension = excluded.file_extension, accessed = excluded.accessed",
+                meta.id,
+                meta.title,
+                doc_type,
+                added,
+                meta.extension,
+                accessed
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            query!("delete from Tag where document = ?", meta.id)
+                .execute(&mut *tx)
+                .await?;
+            for tag in meta.tags.iter() {
+                query!(
+                    "insert into Tag (document, tag) values (?, ?)",
+                    meta.id,
+                    tag
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            journal::record(&mut tx, meta.id, &JournalOperation::Sync(meta.clone())).await?;
+
+            tx.commit().await?;
+        };
+
+        if let Err(e) = res {
+            self.storage.discard_staged(&meta.id).await?;
+            return Err(e);
+        }
+
+        self.storage.apply_staged(&meta.id).await?;
+
+        self.version(
+            meta.id,
+            &meta.title,
+            &doc_type,
+            meta.extension.as_deref(),
+            &meta.tags,
+        )
+        .await
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<()> {
+        let meta = self.get_meta(id).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        query!("delete from Document where id = ?", id)
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from Tag where document = ?", id)
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from PdfInfo where document = ?", id)
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from Outline where document = ?", id)
+            .execute(&mut *tx)
+            .await?;
+        query!("delete from CollectionDocument where document = ?", id)
+            .execute(&mut *tx)
+            .await?;
+
+        journal::record(&mut tx, id, &JournalOperation::Delete(meta)).await?;
+
+        tx.commit().await?;
+
+        // the render is pruned from the cache at the next start
+        if self.storage.exists(&id).await? {
+            self.storage.delete(&id).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn record_synced(&self, peer: Uuid, versions: &HashMap<Uuid, Version>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        query!("delete from SyncLog where peer = ?", peer)
+            .execute(&mut *tx)
+            .await?;
+        for (document, version) in versions {
+            query!(
+                "insert into SyncLog (peer, document, version) values (?, ?, ?)",
+                peer,
+                document,
+                version
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// merges the documents of both replicas using the versions recorded at their last sync.
+/// a document changed on one side replaces the other side's, changes win over deletions
+/// and if both sides changed a document, the version of `local` keeps the id
+/// while the version of `remote` is kept on both sides as a conflict copy.
+/// documents that are the same on both sides are in sync, e.g. on the first sync of copied directories
+pub async fn sync(local: &dyn Replica, remote: &dyn Replica) -> Result<SyncReport> {
+    let local_id = local.id().await?;
+    let remote_id = remote.id().await?;
+    if local_id == remote_id {
+        return Err(eyre!("can't sync a replica with itself"));
+    }
+
+    let local_versions = local.versions().await?;
+    let remote_versions = remote.versions().await?;
+    let local_synced = local.synced_versions(remote_id).await?;
+    let remote_synced = remote.synced_versions(local_id).await?;
+
+    let documents: HashSet<Uuid> = local_versions
+        .keys()
+        .chain(remote_versions.keys())
+        .chain(local_synced.keys())
+        .chain(remote_synced.keys())
+        .copied()
+        .collect();
+
+    // the versions after the sync, they are recorded as what both sides agreed on
+    let mut local_agreed = local_versions.clone();
+    let mut remote_agreed = remote_versions.clone();
+
+    let mut report = SyncReport::default();
+
+    for id in documents {
+        let local_version = local_versions.get(&id);
+        let remote_version = remote_versions.get(&id);
+        let local_changed = local_version != local_synced.get(&id);
+        let remote_changed = remote_version != remote_synced.get(&id);
+
+        match (local_changed, remote_changed) {
+            (false, false) => {}
+            (true, false) | (true, true) if remote_version.is_none() => {
+                if local_version.is_some() {
+                    remote_agreed.insert(id, remote.import(local.export(id).await?).await?);
+                    report.pushed.push(id);
+                }
+            }
+            (false, true) | (true, true) if local_version.is_none() => {
+                if remote_version.is_some() {
+                    local_agreed.insert(id, local.import(remote.export(id).await?).await?);
+                    report.pulled.push(id);
+                }
+            }
+            (true, false) => match local_version {
+                Some(_) => {
+                    remote_agreed.insert(id, remote.import(local.export(id).await?).await?);
+                    report.pushed.push(id);
+                }
+                None => {
+                    remote.remove(id).await?;
+                    remote_agreed.remove(&id);
+                    report.removed_remotely.push(id);
+                }
+            },
+            (false, true) => match remote_version {
+                Some(_) => {
+                    local_agreed.insert(id, local.import(remote.export(id).await?).await?);
+                    report.pulled.push(id);
+                }
+                None => {
+                    local.remove(id).await?;
+                    local_agreed.remove(&id);
+                    report.removed_locally.push(id);
+                }
+            },
+            (true, true) => {
+                let theirs = remote.export(id).await?;
+                // versions of different replicas aren't comparable, the storage backends hash differently
+                if same_content(&local.export(id).await?, &theirs) {
+                    continue;
+                }
+
+                let copy = conflict_copy(theirs)?;
+                let copy_id = copy.meta.id;
+                info!(
+                    "document {} changed on both sides, keeping {} as a copy",
+                    id, copy_id
+                );
+
+                local_agreed.insert(copy_id, local.import(copy.clone()).await?);
+                remote_agreed.insert(copy_id, remote.import(copy).await?);
+                remote_agreed.insert(id, remote.import(local.export(id).await?).await?);
+
+                report.pushed.push(id);
+                report.conflict_copies.push(copy_id);
+            }
+        }
+    }
+
+    local.record_synced(remote_id, &local_agreed).await?;
+    remote.record_synced(local_id, &remote_agreed).await?;
+
+    Ok(report)
+}
+
+/// whether both snapshots have the same metadata, tags and files, versions of different replicas can't tell
+fn same_content(a: &Snapshot, b: &Snapshot) -> bool {
+    fn sorted(snapshot: &Snapshot) -> (Vec<&String>, Vec<(&str, &[u8])>) {
+        let mut tags: Vec<&String> = snapshot.meta.tags.iter().collect();
+        tags.sort();
+        let mut files: Vec<(&str, &[u8])> = snapshot
+            .files
+            .iter()
+            .map(|f| (f.name.as_str(), f.contents.as_slice()))
+            .collect();
+        files.sort();
+        (tags, files)
+    }
+
+    a.meta.title == b.meta.title
+        && a.meta.doc_type == b.meta.doc_type
+        && a.meta.extension == b.meta.extension
+        && sorted(a) == sorted(b)
+}
+
+/// the same document with a new id, the main file is renamed accordingly
+fn conflict_copy(mut snapshot: Snapshot) -> Result<Snapshot> {
+    let id = new_document_id()?;
+    let old_basename = get_document_basename(&snapshot.meta.id, &snapshot.meta.extension);
+
+    for file in snapshot.files.iter_mut() {
+        if file.name == old_basename {
+            file.name = get_document_basename(&id, &snapshot.meta.extension);
+        }
+    }
+    snapshot.meta.title = format!("{} (conflict copy)", snapshot.meta.title);
+    snapshot.meta.id = id;
+
+    Ok(snapshot)
+}
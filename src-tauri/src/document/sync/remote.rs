@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{Replica, Snapshot, Version};
+
+/// the protocol is one JSON request per line, each answered by one JSON response per line
+#[derive(Serialize, Deserialize)]
+enum Request {
+    Id,
+    Versions,
+    SyncedVersions(Uuid),
+    Export(Uuid),
+    Import(Snapshot),
+    Remove(Uuid),
+    RecordSynced(Uuid, HashMap<Uuid, Version>),
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Id(Uuid),
+    Versions(HashMap<Uuid, Version>),
+    Version(Version),
+    Snapshot(Snapshot),
+    Done,
+    Error(String),
+}
+
+/// a replica on the other end of a stream, e.g. a Unix socket, that is answered by [`serve`]
+pub struct RemoteReplica<S> {
+    stream: Mutex<BufStream<S>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> RemoteReplica<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream: Mutex::new(BufStream::new(stream)),
+        }
+    }
+
+    async fn request(&self, request: Request) -> Result<Response> {
+        let mut stream = self.stream.lock().await;
+
+        write_line(&mut *stream, &request).await?;
+
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(eyre!("peer closed the connection"));
+        }
+
+        match serde_json::from_str(&line)? {
+            Response::Error(e) => Err(eyre!("peer failed: {}", e)),
+            response => Ok(response),
+        }
+    }
+
+    async fn request_done(&self, request: Request) -> Result<()> {
+        match self.request(request).await? {
+            Response::Done => Ok(()),
+            _ => Err(eyre!("unexpected response")),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Replica for RemoteReplica<S> {
+    async fn id(&self) -> Result<Uuid> {
+        match self.request(Request::Id).await? {
+            Response::Id(id) => Ok(id),
+            _ => Err(eyre!("unexpected response")),
+        }
+    }
+
+    async fn versions(&self) -> Result<HashMap<Uuid, Version>> {
+        match self.request(Request::Versions).await? {
+            Response::Versions(versions) => Ok(versions),
+            _ => Err(eyre!("unexpected response")),
+        }
+    }
+
+    async fn synced_versions(&self, peer: Uuid) -> Result<HashMap<Uuid, Version>> {
+        match self.request(Request::SyncedVersions(peer)).await? {
+            Response::Versions(versions) => Ok(versions),
+            _ => Err(eyre!("unexpected response")),
+        }
+    }
+
+    async fn export(&self, id: Uuid) -> Result<Snapshot> {
+        match self.request(Request::Export(id)).await? {
+            Response::Snapshot(snapshot) => Ok(snapshot),
+            _ => Err(eyre!("unexpected response")),
+        }
+    }
+
+    async fn import(&self, snapshot: Snapshot) -> Result<Version> {
+        match self.request(Request::Import(snapshot)).await? {
+            Response::Version(version) => Ok(version),
+            _ => Err(eyre!("unexpected response")),
+        }
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<()> {
+        self.request_done(Request::Remove(id)).await
+    }
+
+    async fn record_synced(&self, peer: Uuid, versions: &HashMap<Uuid, Version>) -> Result<()> {
+        self.request_done(Request::RecordSynced(peer, versions.clone()))
+            .await
+    }
+}
+
+/// answers the requests of a [`RemoteReplica`] with `replica` until the connection is closed
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+    replica: &dyn Replica,
+    stream: S,
+) -> Result<()> {
+    let mut stream = BufStream::new(stream);
+
+    let mut line = String::new();
+    while stream.read_line(&mut line).await? != 0 {
+        let response: Result<Response> = try {
+            match serde_json::from_str(&line)? {
+                Request::Id => Response::Id(replica.id().await?),
+                Request::Versions => Response::Versions(replica.versions().await?),
+                Request::SyncedVersions(peer) => {
+                    Response::Versions(replica.synced_versions(peer).await?)
+                }
+                Request::Export(id) => Response::Snapshot(replica.export(id).await?),
+                Request::Import(snapshot) => Response::Version(replica.import(snapshot).await?),
+                Request::Remove(id) => {
+                    replica.remove(id).await?;
+                    Response::Done
+                }
+                Request::RecordSynced(peer, versions) => {
+                    replica.record_synced(peer, &versions).await?;
+                    Response::Done
+                }
+            }
+        };
+
+        write_line(
+            &mut stream,
+            &response.unwrap_or_else(|e| Response::Error(format!("{:?}", e))),
+        )
+        .await?;
+        line.clear();
+    }
+
+    Ok(())
+}
+
+async fn write_line<S: AsyncWrite + Unpin>(stream: &mut S, value: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+    stream.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use tempfile::tempdir;
+use tokio::test;
+
+use crate::document::get_document_basename;
+use crate::document::sync::remote::{serve, RemoteReplica};
+use crate::document::sync::*;
+use crate::types::DocType;
+
+fn snapshot(id: Uuid, title: &str, contents: &str) -> Snapshot {
+    let now = chrono::Utc::now().naive_utc();
+    Snapshot {
+        meta: Meta {
+            title: title.to_string(),
+            doc_type: DocType::Plain,
+            tags: vec!["sync".to_string()],
+            created: now,
+            accessed: now,
+            id,
+            extension: Some("txt".to_string()),
+        },
+        files: vec![SnapshotFile {
+            name: get_document_basename(&id, &Some("txt".to_string())),
+            contents: contents.as_bytes().to_vec(),
+        }],
+    }
+}
+
+async fn titles(replica: &dyn Replica) -> Result<Vec<String>> {
+    let mut titles = vec![];
+    for id in replica.versions().await?.into_keys() {
+        titles.push(replica.export(id).await?.meta.title);
+    }
+    titles.sort();
+    Ok(titles)
+}
+
+#[test]
+async fn sync_directories() {
+    let laptop_dir = tempdir().unwrap();
+    let desktop_dir = tempdir().unwrap();
+
+    if let Err(e) = async {
+        let laptop = LocalReplica::open(laptop_dir.path().to_str().unwrap()).await?;
+        let desktop = LocalReplica::open(desktop_dir.path().to_str().unwrap()).await?;
+
+        let id = new_document_id()?;
+        laptop.import(snapshot(id, "notes", "first")).await?;
+
+        let report = sync(&laptop, &desktop).await?;
+        assert_eq!(report.pushed, vec![id], "new document wasn't pushed");
+        assert_eq!(titles(&desktop).await?, vec!["notes"]);
+
+        desktop.import(snapshot(id, "renamed", "first")).await?;
+        let report = sync(&laptop, &desktop).await?;
+        assert_eq!(report.pulled, vec![id], "change wasn't pulled");
+        assert_eq!(titles(&laptop).await?, vec!["renamed"]);
+
+        laptop.import(snapshot(id, "laptop", "laptop")).await?;
+        desktop.import(snapshot(id, "desktop", "desktop")).await?;
+        let report = sync(&laptop, &desktop).await?;
+        assert_eq!(report.conflict_copies.len(), 1, "no conflict copy");
+        let expected = vec!["desktop (conflict copy)", "laptop"];
+        assert_eq!(titles(&laptop).await?, expected);
+        assert_eq!(titles(&desktop).await?, expected);
+
+        let copy = desktop.export(report.conflict_copies[0]).await?;
+        assert_eq!(copy.files[0].contents, b"desktop");
+
+        laptop.remove(id).await?;
+        let report = sync(&laptop, &desktop).await?;
+        assert_eq!(report.removed_remotely, vec![id], "deletion wasn't synced");
+        assert_eq!(titles(&desktop).await?, vec!["desktop (conflict copy)"]);
+
+        let report = sync(&laptop, &desktop).await?;
+        assert_eq!(
+            report,
+            SyncReport::default(),
+            "second sync changed something"
+        );
+
+        Ok::<(), eyre::Report>(())
+    }
+    .await
+    {
+        panic!("Error: {:?}", e);
+    }
+}
+
+#[test]
+async fn sync_over_stream() {
+    let laptop_dir = tempdir().unwrap();
+    let desktop_dir = tempdir().unwrap();
+
+    if let Err(e) = async {
+        let laptop = LocalReplica::open(laptop_dir.path().to_str().unwrap()).await?;
+        let desktop = LocalReplica::open(desktop_dir.path().to_str().unwrap()).await?;
+
+        let id = new_document_id()?;
+        desktop.import(snapshot(id, "notes", "desktop")).await?;
+
+        let (client, server) = tokio::io::duplex(1024);
+        let remote = RemoteReplica::new(client);
+
+        let (report, served) = tokio::join!(
+            async {
+                let report = sync(&laptop, &remote).await;
+                drop(remote);
+                report
+            },
+            serve(&desktop, server)
+        );
+        served?;
+
+        assert_eq!(report?.pulled, vec![id], "document wasn't pulled");
+        assert_eq!(laptop.export(id).await?.files[0].contents, b"desktop");
+
+        Ok::<(), eyre::Report>(())
+    }
+    .await
+    {
+        panic!("Error: {:?}", e);
+    }
+}
+
+#[test]
+async fn first_sync_of_copies() {
+    let laptop_dir = tempdir().unwrap();
+    let desktop_dir = tempdir().unwrap();
+
+    if let Err(e) = async {
+        let laptop = LocalReplica::open(laptop_dir.path().to_str().unwrap()).await?;
+        let desktop = LocalReplica::open(desktop_dir.path().to_str().unwrap()).await?;
+
+        let id = new_document_id()?;
+        let mut copy = snapshot(id, "notes", "same");
+        laptop.import(copy.clone()).await?;
+        copy.meta.tags = vec!["Sync ".to_string()];
+        desktop.import(copy).await?;
+
+        assert_eq!(desktop.export(id).await?.meta.tags, vec!["sync"]);
+
+        let report = sync(&laptop, &desktop).await?;
+        assert_eq!(
+            report,
+            SyncReport::default(),
+            "documents both sides already had weren't in sync"
+        );
+
+        desktop.import(snapshot(id, "renamed", "same")).await?;
+        let report = sync(&laptop, &desktop).await?;
+        assert_eq!(
+            report.pulled,
+            vec![id],
+            "change after the first sync wasn't pulled"
+        );
+
+        Ok::<(), eyre::Report>(())
+    }
+    .await
+    {
+        panic!("Error: {:?}", e);
+    }
+}
+
+#[test]
+async fn import_files() {
+    let dtmp = tempdir().unwrap();
+    let root = dtmp.path().join("filespider");
+    std::fs::create_dir(&root).unwrap();
+
+    if let Err(e) = async {
+        let replica = LocalReplica::open(root.to_str().unwrap()).await?;
+
+        let id = new_document_id()?;
+        let mut hostile = snapshot(id, "hostile", "hostile");
+        hostile.files.push(SnapshotFile {
+            name: "../../escape".to_string(),
+            contents: b"escape".to_vec(),
+        });
+        assert!(
+            replica.import(hostile).await.is_err(),
+            "hostile name was imported"
+        );
+        assert!(replica.export(id).await.is_err(), "document was created");
+        assert!(!dtmp.path().join("escape").exists());
+        assert!(!dtmp.path().parent().unwrap().join("escape").exists());
+
+        let mut attached = snapshot(id, "notes", "notes");
+        attached.files.push(SnapshotFile {
+            name: "attachment.txt".to_string(),
+            contents: b"attachment".to_vec(),
+        });
+        replica.import(attached).await?;
+        assert_eq!(replica.export(id).await?.files.len(), 2);
+
+        replica.import(snapshot(id, "notes", "notes")).await?;
+        let files = replica.export(id).await?.files;
+        assert_eq!(
+            files.len(),
+            1,
+            "file missing from the snapshot wasn't removed"
+        );
+        assert_eq!(files[0].contents, b"notes");
+
+        Ok::<(), eyre::Report>(())
+    }
+    .await
+    {
+        panic!("Error: {:?}", e);
+    }
+}
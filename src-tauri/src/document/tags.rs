@@ -0,0 +1,458 @@
+use std::sync::RwLock;
+
+use eyre::{eyre, Result};
+use log::info;
+use sqlx::{query, SqliteConnection, SqlitePool};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::settings::TagSettings;
+use crate::types::{JournalOperation, MetaPatch, TagAlias, TagInfo, TagProperties};
+
+use super::{history, journal};
+
+static SETTINGS: RwLock<TagSettings> = RwLock::new(TagSettings {
+    case_folding: true,
+    unicode_nfc: true,
+});
+
+/// changes how tags are normalized from now on, tags that are already stored are normalized by [`normalize_existing`]
+pub fn configure(settings: &TagSettings) {
+    *SETTINGS.write().unwrap() = settings.clone();
+}
+
+/// the form a tag is stored in, without resolving aliases
+pub fn normalize(tag: &str) -> String {
+    let settings = SETTINGS.read().unwrap().clone();
+
+    let tag = tag.trim();
+    let tag: String = match settings.unicode_nfc {
+        true => tag.nfc().collect(),
+        false => tag.to_string(),
+    };
+    match settings.case_folding {
+        true => tag.to_lowercase(),
+        false => tag,
+    }
+}
+
+/// the normalized tag or the tag its alias points to
+pub(super) async fn canonical(connection: &mut SqliteConnection, tag: &str) -> Result<String> {
+    let tag = normalize(tag);
+
+    Ok(query!("select tag from TagAlias where alias = ?", tag)
+        .map(|x| x.tag)
+        .fetch_optional(&mut *connection)
+        .await?
+        .unwrap_or(tag))
+}
+
+/// canonical forms of `tags` without duplicates
+pub(super) async fn canonical_all(
+    connection: &mut SqliteConnection,
+    tags: Vec<String>,
+) -> Result<Vec<String>> {
+    let mut canonical_tags = vec![];
+    for tag in tags {
+        let tag = canonical(connection, &tag).await?;
+        if !canonical_tags.contains(&tag) {
+            canonical_tags.push(tag);
+        }
+    }
+    Ok(canonical_tags)
+}
+
+/// the patch with the canonical form of its tag
+pub(crate) async fn canonical_patch(
+    connection: &mut SqliteConnection,
+    patch: MetaPatch,
+) -> Result<MetaPatch> {
+    Ok(match patch {
+        MetaPatch::AddTag(tag) => MetaPatch::AddTag(canonical(connection, &tag).await?),
+        MetaPatch::RemoveTag(tag) => MetaPatch::RemoveTag(canonical(connection, &tag).await?),
+        patch => patch,
+    })
+}
+
+pub async fn get_aliases(pool: &SqlitePool) -> Result<Vec<TagAlias>> {
+    Ok(
+        query!("select alias, tag from TagAlias order by tag, alias")
+            .map(|x| TagAlias {
+                alias: x.alias,
+                tag: x.tag,
+            })
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
+/// documents can't have a tag that is an alias, tags that are in use have to be merged instead
+pub async fn add_alias(pool: &SqlitePool, alias: String, tag: String) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let alias = normalize(&alias);
+    let tag = canonical(&mut tx, &tag).await?;
+    if alias == tag {
+        return Err(eyre!("{} can't be an alias of itself", alias));
+    }
+
+    if query!("select document from Tag where tag = ? limit 1", alias)
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some()
+    {
+        return Err(eyre!("tag {} is in use, merge it instead", alias));
+    }
+
+    set_alias(&mut tx, &alias, &tag).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn remove_alias(pool: &SqlitePool, alias: String) -> Result<()> {
+    let alias = normalize(&alias);
+
+    match query!("delete from TagAlias where alias = ?", alias)
+        .execute(pool)
+        .await?
+        .rows_affected()
+    {
+        1 => Ok(()),
+        _ => Err(eyre!("{} is not an alias", alias)),
+    }
+}
+
+/// moves every document with the tag `from` to `to` and makes `from` an alias of `to`,
+/// operations on `from` that can be undone or redone affect `to` afterwards
+pub async fn merge(pool: &SqlitePool, from: String, to: String) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let from = normalize(&from);
+    let to = canonical(&mut tx, &to).await?;
+    if from == to {
+        return Err(eyre!("can't merge {} into itself", from));
+    }
+
+    let documents = query!(
+        r#"select document as "document!: Uuid" from Tag where tag = ?"#,
+        from
+    )
+    .map(|x| x.document)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for id in documents {
+        query!("delete from Tag where document = ? and tag = ?", id, from)
+            .execute(&mut *tx)
+            .await?;
+        record_remove(&mut tx, id, &from).await?;
+
+        if query!(
+            "insert or ignore into Tag (document, tag) values (?, ?)",
+            id,
+            to
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            == 1
+        {
+            record_add(&mut tx, id, &to).await?;
+        }
+    }
+
+    set_alias(&mut tx, &from, &to).await?;
+    history::replace_tag(&mut tx, &from, Some(&to)).await?;
+
+    tx.commit().await?;
+
+    info!("merged tag {} into {}", from, to);
+
+    Ok(())
+}
+
+/// all tags, pinned ones first
+pub async fn get_all(pool: &SqlitePool) -> Result<Vec<TagInfo>> {
+    Ok(query!(
+        r#"select name, color, icon, description, pinned, coalesce(counts.documents, 0) as "documents!: u32"
+        from TagInfo left join (select tag, count(document) as documents from Tag group by tag) as counts on counts.tag = TagInfo.name
+        order by pinned desc, name"#
+    )
+    .map(|x| TagInfo {
+        name: x.name,
+        properties: TagProperties {
+            color: x.color,
+            icon: x.icon,
+            description: x.description,
+            pinned: x.pinned,
+        },
+        documents: x.documents,
+    })
+    .fetch_all(pool)
+    .await?)
+}
+
+/// the tags in `names` that exist, pinned ones first
+pub async fn get_info(pool: &SqlitePool, names: &[String]) -> Result<Vec<TagInfo>> {
+    let names = serde_json::to_string(names)?;
+
+    Ok(query!(
+        r#"select name, color, icon, description, pinned, (select count(document) from Tag where tag = TagInfo.name) as "documents!: u32"
+        from TagInfo where name in (select value from json_each(?))
+        order by pinned desc, name"#,
+        names
+    )
+    .map(|x| TagInfo {
+        name: x.name,
+        properties: TagProperties {
+            color: x.color,
+            icon: x.icon,
+            description: x.description,
+            pinned: x.pinned,
+        },
+        documents: x.documents,
+    })
+    .fetch_all(pool)
+    .await?)
+}
+
+/// creates the tag if it doesn't exist yet, so it can be used before any document has it
+pub async fn set_properties(
+    pool: &SqlitePool,
+    name: String,
+    properties: TagProperties,
+) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+
+    let name = canonical(&mut connection, &name).await?;
+    if name.is_empty() {
+        return Err(eyre!("tags can't be empty"));
+    }
+
+    query!(
+        "insert into TagInfo (name, color, icon, description, pinned) values (?, ?, ?, ?, ?)
+        on conflict(name) do update set color = excluded.color, icon = excluded.icon, description = excluded.description, pinned = excluded.pinned",
+        name,
+        properties.color,
+        properties.icon,
+        properties.description,
+        properties.pinned
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// renames the tag on every document and in the history, aliases of the old name point to the new one
+pub async fn rename(pool: &SqlitePool, name: String, new_name: String) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let name = canonical(&mut tx, &name).await?;
+    let new_name = normalize(&new_name);
+    if new_name.is_empty() {
+        return Err(eyre!("tags can't be empty"));
+    }
+    if canonical(&mut tx, &new_name).await? != new_name
+        || query!("select name from TagInfo where name = ?", new_name)
+            .fetch_optional(&mut *tx)
+            .await?
+            .is_some()
+    {
+        return Err(eyre!("tag {} already exists, merge it instead", new_name));
+    }
+
+    if query!("update TagInfo set name = ? where name = ?", new_name, name)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(eyre!("no tag named {}", name));
+    }
+
+    let documents = query!(
+        r#"select document as "document!: Uuid" from Tag where tag = ?"#,
+        name
+    )
+    .map(|x| x.document)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for id in documents {
+        query!(
+            "update Tag set tag = ? where document = ? and tag = ?",
+            new_name,
+            id,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+        record_remove(&mut tx, id, &name).await?;
+        record_add(&mut tx, id, &new_name).await?;
+    }
+
+    query!("update TagAlias set tag = ? where tag = ?", new_name, name)
+        .execute(&mut *tx)
+        .await?;
+    history::replace_tag(&mut tx, &name, Some(&new_name)).await?;
+
+    tx.commit().await?;
+
+    info!("renamed tag {} to {}", name, new_name);
+
+    Ok(())
+}
+
+/// removes the tag from every document, its aliases and its properties, operations on it can't be undone anymore
+pub async fn delete(pool: &SqlitePool, name: String) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let name = canonical(&mut tx, &name).await?;
+
+    if query!("delete from TagInfo where name = ?", name)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(eyre!("no tag named {}", name));
+    }
+
+    let documents = query!(
+        r#"select document as "document!: Uuid" from Tag where tag = ?"#,
+        name
+    )
+    .map(|x| x.document)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for id in documents {
+        query!("delete from Tag where document = ? and tag = ?", id, name)
+            .execute(&mut *tx)
+            .await?;
+        record_remove(&mut tx, id, &name).await?;
+    }
+
+    query!("delete from TagAlias where tag = ?", name)
+        .execute(&mut *tx)
+        .await?;
+    history::replace_tag(&mut tx, &name, None).await?;
+
+    tx.commit().await?;
+
+    info!("deleted tag {}", name);
+
+    Ok(())
+}
+
+async fn record_add(connection: &mut SqliteConnection, id: Uuid, tag: &str) -> Result<()> {
+    journal::record(
+        connection,
+        id,
+        &JournalOperation::Patch {
+            patch: MetaPatch::AddTag(tag.to_string()),
+            inverse: MetaPatch::RemoveTag(tag.to_string()),
+        },
+    )
+    .await
+}
+
+async fn record_remove(connection: &mut SqliteConnection, id: Uuid, tag: &str) -> Result<()> {
+    journal::record(
+        connection,
+        id,
+        &JournalOperation::Patch {
+            patch: MetaPatch::RemoveTag(tag.to_string()),
+            inverse: MetaPatch::AddTag(tag.to_string()),
+        },
+    )
+    .await
+}
+
+/// aliases of `alias` are moved to `tag`, so aliases never point to other aliases
+async fn set_alias(connection: &mut SqliteConnection, alias: &str, tag: &str) -> Result<()> {
+    query!("delete from TagInfo where name = ?", alias)
+        .execute(&mut *connection)
+        .await?;
+
+    query!("update TagAlias set tag = ? where tag = ?", tag, alias)
+        .execute(&mut *connection)
+        .await?;
+
+    query!(
+        "insert into TagAlias (alias, tag) values (?, ?) on conflict(alias) do update set tag = excluded.tag",
+        alias,
+        tag
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// rewrites tags that were stored before they were normalized or with other settings
+pub async fn normalize_existing(pool: &SqlitePool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    for tag in query!("select name from TagInfo")
+        .map(|x| x.name)
+        .fetch_all(&mut *tx)
+        .await?
+    {
+        let canonical = canonical(&mut tx, &tag).await?;
+        if canonical == tag {
+            continue;
+        }
+
+        // before the tags are moved, so the properties are kept unless the canonical tag has its own
+        query!(
+            "update or ignore TagInfo set name = ? where name = ?",
+            canonical,
+            tag
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!("delete from TagInfo where name = ?", tag)
+            .execute(&mut *tx)
+            .await?;
+
+        // like merge, so the journal knows which documents changed
+        let documents = query!(
+            r#"select document as "document!: Uuid" from Tag where tag = ?"#,
+            tag
+        )
+        .map(|x| x.document)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for id in documents {
+            query!("delete from Tag where document = ? and tag = ?", id, tag)
+                .execute(&mut *tx)
+                .await?;
+            record_remove(&mut tx, id, &tag).await?;
+
+            if query!(
+                "insert or ignore into Tag (document, tag) values (?, ?)",
+                id,
+                canonical
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                == 1
+            {
+                record_add(&mut tx, id, &canonical).await?;
+            }
+        }
+        history::replace_tag(&mut tx, &tag, Some(&canonical)).await?;
+
+        info!("normalized tag {} to {}", tag, canonical);
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use eyre::{eyre, Result};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::directories::get_presets_directory;
+use crate::settings::DocumentPreset;
+use crate::types::DocType;
+
+use super::{create_document, get_document_basename, get_meta, storage, File, NewDocument};
+
+/// filled in for every template, presets can't declare variables with these names
+pub const BUILTIN_VARIABLES: [&str; 4] = ["title", "date", "tags", "id"];
+
+/// creates a document from the files of the preset, `tags` are added to the tags of the preset
+/// and `variables` needs a value for every variable the preset declares
+pub async fn create(
+    pool: &SqlitePool,
+    preset: &DocumentPreset,
+    title: String,
+    tags: Vec<String>,
+    mut variables: HashMap<String, String>,
+) -> Result<Uuid> {
+    for variable in preset.variables.iter() {
+        if BUILTIN_VARIABLES.contains(&variable.as_str()) {
+            return Err(eyre!(
+                "preset {} declares the builtin variable {}",
+                preset.name,
+                variable
+            ));
+        }
+        if !variables.contains_key(variable) {
+            return Err(eyre!("no value for variable {}", variable));
+        }
+    }
+    variables.retain(|name, _| preset.variables.contains(name));
+
+    let mut preset_tags = preset.tags.clone();
+    preset_tags.extend(tags);
+
+    create_document(
+        pool,
+        NewDocument {
+            title,
+            doc_type: preset.doc_type.clone().unwrap_or(DocType::Plain),
+            tags: preset_tags,
+            extension: preset.extension.clone(),
+            file: preset.file.clone(),
+            attachments: preset.attachments.clone(),
+            pdf_info: None,
+            source: None,
+            variables: Some(variables),
+        },
+    )
+    .await
+}
+
+/// a preset with copies of all files of the document ("save as template").
+/// the copies are kept in their own directory in the presets directory, so the settings stay small
+pub async fn preset_from_document(
+    pool: &SqlitePool,
+    id: Uuid,
+    name: String,
+) -> Result<DocumentPreset> {
+    let meta = get_meta(pool, id).await?;
+    let main = get_document_basename(&id, &meta.extension);
+
+    let directory = format!("{}/{}", get_presets_directory()?, Uuid::new_v4().simple());
+    tokio::fs::create_dir_all(&directory).await?;
+
+    let mut file = File::None;
+    let mut attachments = vec![];
+    for file_name in storage::get()?.list(&id).await? {
+        let path = format!("{}/{}", directory, file_name);
+        tokio::fs::write(&path, storage::get()?.read(&id, &file_name).await?).await?;
+        let content = File::Path(path);
+        match file_name == main {
+            true => file = content,
+            false => attachments.push((file_name, content)),
+        }
+    }
+
+    Ok(DocumentPreset {
+        name,
+        tags: meta.tags,
+        extension: meta.extension,
+        doc_type: Some(meta.doc_type),
+        file,
+        attachments,
+        variables: vec![],
+    })
+}
+
+/// removes the copies [`preset_from_document`] made for a deleted preset
+/// unless one of the remaining `presets` still refers to them
+pub async fn remove_files(preset: &DocumentPreset, presets: &[DocumentPreset]) -> Result<()> {
+    let root = get_presets_directory()?;
+    let directories = |preset: &DocumentPreset| -> HashSet<String> {
+        std::iter::once(&preset.file)
+            .chain(preset.attachments.iter().map(|(_, file)| file))
+            .filter_map(|file| match file {
+                File::Path(path) => Path::new(path).parent(),
+                _ => None,
+            })
+            .filter(|directory| directory.parent() == Some(Path::new(&root)))
+            .map(|directory| directory.to_string_lossy().to_string())
+            .collect()
+    };
+
+    let used: HashSet<String> = presets.iter().flat_map(directories).collect();
+    for directory in directories(preset) {
+        if !used.contains(&directory) {
+            tokio::fs::remove_dir_all(directory).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// replaces `{{name}}` placeholders in a single pass, so values are never filled in again.
+/// unknown placeholders and files that aren't UTF-8 like images are left as they are
+pub(super) fn fill(
+    content: Vec<u8>,
+    id: Uuid,
+    title: &str,
+    tags: &[String],
+    variables: &HashMap<String, String>,
+) -> Vec<u8> {
+    let text = match String::from_utf8(content) {
+        Ok(text) => text,
+        Err(e) => return e.into_bytes(),
+    };
+
+    let builtins = [
+        ("title", title.to_string()),
+        ("date", chrono::Local::now().format("%Y-%m-%d").to_string()),
+        ("tags", tags.join(", ")),
+        ("id", id.to_string()),
+    ];
+    let value = |name: &str| {
+        builtins
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v)
+            .or_else(|| variables.get(name))
+    };
+
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let placeholder = rest[2..]
+            .find("}}")
+            .and_then(|end| Some((end, value(&rest[2..2 + end])?)));
+        match placeholder {
+            Some((end, value)) => {
+                filled.push_str(value);
+                rest = &rest[end + 4..];
+            }
+            // e.g. the braces of LaTeX in `\title{{{title}}}`, the placeholder can start at the next one
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+
+    filled.into_bytes()
+}
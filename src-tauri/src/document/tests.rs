@@ -1,22 +1,22 @@
+use futures::FutureExt;
 use tempfile::tempdir;
 use tokio::test;
 
 use crate::directories;
-use crate::document::render::render;
+use crate::document::render::{render, Renderers};
 use crate::document::*;
+use crate::settings::{DocumentPreset, TagRule};
+use crate::FilespiderState;
 
 #[test]
 async fn tests() {
-    let dtmp = tempdir().unwrap();
-    let tempdir = dtmp.path();
+    let (guard, tempdir) = crate::test_data_directory().await;
 
     if let Err(e) = async {
         env_logger::Builder::new()
             .filter_level(log::LevelFilter::Trace)
             .init();
 
-        std::env::set_var("FILESPIDER_DATA_PATH", tempdir);
-
         let pool = crate::db::init().await?;
 
         directories::create_directories().await?;
@@ -58,6 +58,50 @@ async fn tests() {
             "failed to add tag"
         );
 
+        assert!(
+            patch_meta(&pool, id, MetaPatch::AddTag("Amogus ".to_string()))
+                .await
+                .is_err(),
+            "tag wasn't normalized"
+        );
+
+        tags::merge(&pool, "amogus".to_string(), "sus".to_string()).await?;
+        let meta = get_meta(&pool, id).await?;
+        assert!(
+            meta.tags.contains(&"sus".to_string()) && !meta.tags.contains(&"amogus".to_string()),
+            "failed to merge tags"
+        );
+        assert!(
+            patch_meta(&pool, id, MetaPatch::AddTag("AMOGUS".to_string()))
+                .await
+                .is_err(),
+            "alias wasn't resolved"
+        );
+
+        tags::set_properties(
+            &pool,
+            "sus".to_string(),
+            TagProperties {
+                pinned: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+        tags::rename(&pool, "sus".to_string(), "sussy".to_string()).await?;
+        let infos = tags::get_all(&pool).await?;
+        assert!(
+            infos[0].name == "sussy" && infos[0].properties.pinned && infos[0].documents == 1,
+            "tag {:?} wasn't renamed with its properties",
+            infos[0]
+        );
+        assert!(
+            get_meta(&pool, id)
+                .await?
+                .tags
+                .contains(&"sussy".to_string()),
+            "failed to rename tag"
+        );
+
         patch_meta(&pool, id, MetaPatch::RemoveTag("abc".to_string())).await?;
 
         let meta = get_meta(&pool, id).await?;
@@ -80,34 +124,856 @@ async fn tests() {
             "ex".to_string(),
             0,
             1,
-            (SearchSortCriterium::CreationTime, false),
+            vec![(SearchSortCriterium::CreationTime, false)],
         )
         .await?;
 
+        assert_eq!(res.total, 1, "search matched something else");
+        assert_eq!(
+            res.tags
+                .iter()
+                .map(|f| (f.value.as_str(), f.count))
+                .collect::<Vec<_>>(),
+            vec![("sussy", 1), ("test", 1)],
+            "tag facets {:?} don't match",
+            res.tags
+        );
+        assert!(
+            res.doc_types.len() == 1
+                && res.doc_types[0].value == DocType::Plain
+                && res.doc_types[0].count == 1,
+            "type facets {:?} don't match",
+            res.doc_types
+        );
+        let res = res.results;
+
         assert_ne!(res.first(), None, "search didn't return anything");
 
         assert_eq!(res[0].title, "exam", "search returned something else");
 
+        let second = create(
+            &pool,
+            "second".to_string(),
+            Some(DocType::Plain),
+            vec![],
+            None,
+            File::None,
+        )
+        .await?;
+        let res = search(
+            &pool,
+            vec![],
+            vec!["test".to_string()],
+            String::new(),
+            0,
+            10,
+            vec![],
+        )
+        .await?;
+        assert!(
+            res.total == 1 && res.tags.is_empty(),
+            "tag facets {:?} contain the excluded document",
+            res.tags
+        );
+        assert!(
+            res.doc_types.len() == 1 && res.doc_types[0].count == 1,
+            "type facets {:?} contain the excluded document",
+            res.doc_types
+        );
+
+        collection::create(&pool, "lectures".to_string()).await?;
+        collection::insert(&pool, "lectures".to_string(), id, None).await?;
+        collection::insert(&pool, "lectures".to_string(), second, Some(0)).await?;
+        collection::reorder(&pool, "lectures".to_string(), vec![id, second]).await?;
+        let res = search(
+            &pool,
+            vec![],
+            vec![],
+            String::new(),
+            0,
+            10,
+            vec![(
+                SearchSortCriterium::CollectionOrder("lectures".to_string()),
+                true,
+            )],
+        )
+        .await?;
+        assert_eq!(
+            res.results.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![id, second],
+            "search didn't return the collection order"
+        );
+
+        assert!(
+            crate::db::natural_cmp("lecture 9", "Lecture 10").is_lt(),
+            "titles aren't sorted naturally"
+        );
+
         let tags_res = get_tags(&pool, "te".to_string()).await?;
         assert!(
             tags_res.len() == 1 && tags_res[0] == "test",
             "tag search failed"
         );
 
-        tokio::fs::write(get_document_file(&meta.id, &meta.extension)?, "testogus").await?;
+        storage::get()?
+            .write(
+                &meta.id,
+                &get_document_basename(&meta.id, &meta.extension),
+                b"testogus",
+            )
+            .await?;
 
-        let path = render(&pool, &mut HashMap::new(), id).await?;
+        let path = render(&pool, &Renderers::default(), id, u64::MAX).await?;
 
         assert_eq!(path.1, RenderType::Plain);
         assert_eq!(tokio::fs::read_to_string(path.0).await?, "testogus");
 
+        // the renderer only finishes when it is released, requests have to be served in the meantime
+        storage::get()?
+            .write(
+                &meta.id,
+                &get_document_basename(&meta.id, &meta.extension),
+                b"concurrent",
+            )
+            .await?;
+        let state = FilespiderState::new(
+            pool.clone(),
+            Settings::default().await?,
+            None,
+            #[cfg(target_os = "linux")]
+            None,
+        );
+        let hash = render::hash_document_files(id).await?;
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let renderer_pool = pool.clone();
+        state.renderers.lock().await.insert(
+            (id, hash),
+            async move {
+                let rendered: Result<()> = async {
+                    released.await?;
+                    tokio::fs::write(get_cache_file(id)?, "concurrent").await?;
+                    let hex_hash = format!("{:016x}", hash);
+                    let now = chrono::Utc::now();
+                    query!(
+                        "insert into Cache (document, hash, render_type, accessed) values (?, unhex(?), 'plain', ?)
+                        on conflict(document) do update set hash = excluded.hash, accessed = excluded.accessed",
+                        id,
+                        hex_hash,
+                        now
+                    )
+                    .execute(&renderer_pool)
+                    .await?;
+                    Ok(())
+                }
+                .await;
+                rendered.map_err(|e| e.to_string())
+            }
+            .boxed()
+            .shared(),
+        );
+
+        let rendering = render(&state.pool, &state.renderers, id, u64::MAX);
+        tokio::pin!(rendering);
+        tokio::select! {
+            _ = &mut rendering => panic!("render finished before the renderer"),
+            served = async {
+                search(&state.pool, vec![], vec![], String::new(), 0, 10, vec![]).await?;
+                update_accessed(&state.pool, id).await?;
+                get_meta(&state.pool, id).await
+            } => assert_eq!(served?.id, id),
+        }
+        release.send(()).unwrap();
+        let path = rendering.await?;
+        assert_eq!(tokio::fs::read_to_string(path.0).await?, "concurrent");
+
         delete(&pool, id).await?;
 
+        history::undo(&pool).await?;
+        assert_eq!(
+            get_meta(&pool, id).await?.title,
+            "exam",
+            "failed to undo deletion"
+        );
+        assert_eq!(
+            collection::get_all(&pool).await?[0].documents,
+            vec![id, second],
+            "collection membership wasn't restored"
+        );
+        history::undo(&pool).await?;
+        assert_eq!(
+            get_meta(&pool, id).await?.title,
+            "Test",
+            "failed to undo title change"
+        );
+        history::redo(&pool).await?;
+        history::redo(&pool).await?;
+        assert!(
+            document_exists(&id).await.is_err(),
+            "failed to redo deletion"
+        );
+
+        let journal = journal::get(&pool, Some(id), None, None, 100).await?;
+        assert!(
+            matches!(journal[0].operation, JournalOperation::Delete(_))
+                && matches!(
+                    journal.last().unwrap().operation,
+                    JournalOperation::Create(_)
+                ),
+            "journal {:?} does not match",
+            journal
+        );
+
+        history::undo(&pool).await?;
+        history::undo(&pool).await?;
+        // e.g. a sync added the tag again whose removal would be undone next
+        query!("insert into Tag (document, tag) values (?, 'abc')", id)
+            .execute(&pool)
+            .await?;
+        history::undo(&pool).await?;
+        assert!(
+            get_meta(&pool, id)
+                .await?
+                .tags
+                .contains(&"abc".to_string()),
+            "conflicting operation wasn't skipped"
+        );
+        assert!(
+            !get_meta(&pool, id)
+                .await?
+                .tags
+                .contains(&"sussy".to_string()),
+            "adding the merged and renamed tag wasn't undone"
+        );
+
+        auto_tag::configure(&[TagRule {
+            tags: vec!["homework".to_string()],
+            doc_type: Some(DocType::Plain),
+            title: Some(r"Blatt \d+".to_string()),
+            content: Some("integral".to_string()),
+            source_folder: None,
+            metadata: Default::default(),
+        }])?;
+        let sheet = create(
+            &pool,
+            "Blatt 3".to_string(),
+            Some(DocType::Plain),
+            vec![],
+            None,
+            File::Blob(b"an integral".to_vec()),
+        )
+        .await?;
+        let draft = create(
+            &pool,
+            "Draft".to_string(),
+            Some(DocType::Plain),
+            vec![],
+            None,
+            File::Blob(b"an integral".to_vec()),
+        )
+        .await?;
+        assert!(
+            get_meta(&pool, sheet)
+                .await?
+                .tags
+                .contains(&"homework".to_string()),
+            "tag rule wasn't applied on create"
+        );
+        patch_meta(&pool, draft, MetaPatch::ChangeTitle("Blatt 4".to_string())).await?;
+        assert!(
+            get_meta(&pool, draft)
+                .await?
+                .tags
+                .contains(&"homework".to_string()),
+            "tag rule wasn't applied on edit"
+        );
+        let report = auto_tag::apply_all(&pool).await?;
+        assert!(
+            report.added == 0 && report.failed.is_empty(),
+            "re-applying the tag rules reported {:?}",
+            report
+        );
+        auto_tag::configure(&[])?;
+
+        let notes = create(
+            &pool,
+            "Notes".to_string(),
+            Some(DocType::Plain),
+            vec![],
+            None,
+            File::Blob(b"another integral".to_vec()),
+        )
+        .await?;
+        let suggestions = suggest::suggest(&pool, notes, 5).await?;
+        assert!(
+            suggestions.first().is_some_and(|s| s.tag == "homework"),
+            "suggestions {:?} don't match",
+            suggestions
+        );
+
+        let mut homework = SavedSearch {
+            name: "homework".to_string(),
+            pos_filter: vec!["homework".to_string()],
+            neg_filter: vec![],
+            crib: String::new(),
+            sort: vec![(SearchSortCriterium::Title, true)],
+            limit: None,
+        };
+        saved_search::create(&pool, homework.clone()).await?;
+        let titles = |metas: Vec<Meta>| metas.into_iter().map(|m| m.title).collect::<Vec<_>>();
+        assert_eq!(
+            titles(saved_search::evaluate(&pool, "homework".to_string(), 0, 10).await?),
+            vec!["Blatt 3", "Blatt 4"],
+            "saved search doesn't match"
+        );
+        homework.limit = Some(1);
+        saved_search::update(&pool, "homework".to_string(), homework).await?;
+        assert_eq!(
+            titles(saved_search::evaluate(&pool, "homework".to_string(), 0, 10).await?),
+            vec!["Blatt 3"],
+            "limit wasn't applied"
+        );
+        assert!(
+            saved_search::evaluate(&pool, "homework".to_string(), 1, 1)
+                .await?
+                .is_empty(),
+            "page past the limit isn't empty"
+        );
+        let res = search(
+            &pool,
+            vec!["search:homework".to_string()],
+            vec![],
+            String::new(),
+            0,
+            10,
+            vec![],
+        )
+        .await?;
+        assert_eq!(titles(res.results), vec!["Blatt 3"], "virtual tag doesn't match");
+        let res = search(
+            &pool,
+            vec!["homework".to_string()],
+            vec!["search:homework".to_string()],
+            String::new(),
+            0,
+            10,
+            vec![],
+        )
+        .await?;
+        assert_eq!(
+            titles(res.results),
+            vec!["Blatt 4"],
+            "negated virtual tag doesn't match"
+        );
+        saved_search::create(
+            &pool,
+            SavedSearch {
+                name: "cycle".to_string(),
+                pos_filter: vec!["search:cycle".to_string()],
+                neg_filter: vec![],
+                crib: String::new(),
+                sort: vec![],
+                limit: None,
+            },
+        )
+        .await?;
+        assert!(
+            saved_search::evaluate(&pool, "cycle".to_string(), 0, 10)
+                .await
+                .is_err(),
+            "cycle wasn't stopped"
+        );
+
+        let preset = DocumentPreset {
+            name: "Sheet".to_string(),
+            tags: vec!["sheet".to_string()],
+            extension: Some("md".to_string()),
+            doc_type: Some(DocType::Markdown),
+            file: File::Blob(b"# {{title}} for {{course}}".to_vec()),
+            attachments: vec![("id.txt".to_string(), File::Blob(b"{{id}}".to_vec()))],
+            variables: vec!["course".to_string()],
+        };
+        assert!(
+            template::create(
+                &pool,
+                &preset,
+                "Blatt 1".to_string(),
+                vec![],
+                HashMap::new()
+            )
+            .await
+            .is_err(),
+            "missing variable wasn't rejected"
+        );
+        let sheet = template::create(
+            &pool,
+            &preset,
+            "Blatt 1".to_string(),
+            vec![],
+            HashMap::from([("course".to_string(), "Analysis".to_string())]),
+        )
+        .await?;
+        assert_eq!(
+            storage::get()?
+                .read(&sheet, &get_document_basename(&sheet, &preset.extension))
+                .await?,
+            b"# Blatt 1 for Analysis",
+            "template wasn't filled in"
+        );
+        assert_eq!(
+            storage::get()?.read(&sheet, "id.txt").await?,
+            sheet.to_string().as_bytes(),
+            "attachment wasn't filled in"
+        );
+
+        let saved = template::preset_from_document(&pool, sheet, "Saved".to_string()).await?;
+        let presets = directories::get_presets_directory()?;
+        assert!(
+            std::iter::once(&saved.file)
+                .chain(saved.attachments.iter().map(|(_, f)| f))
+                .all(|f| matches!(f, File::Path(p) if p.starts_with(&presets))),
+            "files of the saved preset weren't stored in the presets directory"
+        );
+        let copy = template::create(&pool, &saved, "Copy".to_string(), vec![], HashMap::new()).await?;
+        assert_eq!(
+            storage::get()?.read(&copy, "id.txt").await?,
+            sheet.to_string().as_bytes(),
+            "saved preset doesn't contain the files of the document"
+        );
+        template::remove_files(&saved, &[]).await?;
+        assert!(
+            tokio::fs::read_dir(&presets).await?.next_entry().await?.is_none(),
+            "files of the deleted preset weren't removed"
+        );
+
+        let mut settings = Settings::default().await?;
+        settings.validate()?;
+        settings.presets.push(preset);
+        settings.presets[0].attachments = vec![("../escape".to_string(), File::None)];
+        assert!(
+            settings.validate().is_err(),
+            "attachment name with a path wasn't rejected"
+        );
+        settings.presets[0].attachments = vec![];
+        settings.presets.push(settings.presets[0].clone());
+        assert!(
+            settings.validate().is_err(),
+            "duplicate preset names weren't rejected"
+        );
+
+        assert!(fsck::fsck(&pool, false).await?.is_clean(), "DB isn't clean");
+        let ghost = Uuid::new_v4();
+        let now = chrono::Utc::now();
+        let patch = serde_json::to_string(&JournalOperation::Patch {
+            patch: MetaPatch::ChangeTitle("new".to_string()),
+            inverse: MetaPatch::ChangeTitle("old".to_string()),
+        })?;
+        query!("insert into Tag (document, tag) values (?, 'ghost')", ghost)
+            .execute(&pool)
+            .await?;
+        query!(
+            "insert into CollectionDocument (collection, document, position) values ('lectures', ?, 5)",
+            ghost
+        )
+        .execute(&pool)
+        .await?;
+        query!(
+            "insert into Journal (time, document, operation) values (?, ?, 'garbage')",
+            now,
+            ghost
+        )
+        .execute(&pool)
+        .await?;
+        query!(
+            "insert into History (stack, document, operation) values ('undo', ?, ?)",
+            ghost,
+            patch
+        )
+        .execute(&pool)
+        .await?;
+        query!("delete from TagInfo where name = 'homework'")
+            .execute(&pool)
+            .await?;
+
+        let report = fsck::fsck(&pool, false).await?;
+        assert!(
+            report.dangling_tags == vec![(ghost, "ghost".to_string())]
+                && report.dangling_collection_entries == vec![("lectures".to_string(), ghost)]
+                && report.invalid_journal_entries.len() == 1
+                && report.stale_history_entries.len() == 1
+                && report.inconsistent_tag_info == vec!["homework"]
+                && !report.repaired,
+            "fsck report {:?} does not match",
+            report
+        );
+        assert!(
+            fsck::fsck(&pool, true).await?.repaired,
+            "fsck didn't repair"
+        );
+        let report = fsck::fsck(&pool, false).await?;
+        assert!(report.is_clean(), "fsck left {:?}", report);
+        assert!(
+            tags::get_all(&pool)
+                .await?
+                .iter()
+                .any(|t| t.name == "homework"),
+            "tag info wasn't recreated"
+        );
+
+        assert_eq!(
+            pdf_import::split_keywords("math; analysis, math ,"),
+            vec!["math", "analysis"]
+        );
+        assert_eq!(pdf_import::split_keywords("a  b c"), vec!["a", "b", "c"]);
+        assert!(pdf_import::split_keywords("  ").is_empty());
+
+        let path = tempdir.join("Lecture 3.pdf");
+        tokio::fs::write(
+            &path,
+            pdf("/Title (Lecture Notes) /Keywords (math; analysis)"),
+        )
+        .await?;
+        let path = path.to_string_lossy().to_string();
+        let notes = import_pdf(&pool, None, vec![], &File::Path(path.clone())).await?;
+        let info = get_pdf_info(&pool, notes).await?;
+        assert_eq!(
+            info.title.as_deref(),
+            Some("Lecture Notes"),
+            "title wasn't read from the info"
+        );
+        assert_eq!(info.keywords, vec!["math", "analysis"]);
+        assert_eq!(
+            info.outline,
+            vec![
+                OutlineEntry {
+                    title: "Intro".to_string(),
+                    depth: 0,
+                    page: Some(0)
+                },
+                OutlineEntry {
+                    title: "Motivation".to_string(),
+                    depth: 1,
+                    page: None
+                },
+                OutlineEntry {
+                    title: "Proofs".to_string(),
+                    depth: 0,
+                    page: Some(1)
+                },
+            ]
+        );
+
+        tokio::fs::write(&path, pdf("/Author (Me)")).await?;
+        let untitled = import_pdf(&pool, None, vec![], &File::Path(path)).await?;
+        assert_eq!(
+            get_meta(&pool, untitled).await?.title,
+            "Lecture 3",
+            "title didn't fall back to the file name"
+        );
+        let keywords = query!("select keywords from PdfInfo where document = ?", untitled)
+            .fetch_one(&pool)
+            .await?
+            .keywords;
+        assert_eq!(keywords, None, "empty keywords weren't stored as NULL");
+        let untitled = import_pdf(&pool, None, vec![], &File::Blob(pdf(""))).await?;
+        assert_eq!(get_meta(&pool, untitled).await?.title, "Untitled");
+
+        cache::clear(&pool).await?;
+        let mut renders = vec![];
+        for title in ["first", "second", "third"] {
+            renders.push(
+                create(
+                    &pool,
+                    title.to_string(),
+                    Some(DocType::Plain),
+                    vec![],
+                    None,
+                    File::Blob(vec![b'x'; 100]),
+                )
+                .await?,
+            );
+        }
+        render(&pool, &Renderers::default(), renders[0], u64::MAX).await?;
+        render(&pool, &Renderers::default(), renders[1], u64::MAX).await?;
+        // a cache hit makes the first render the more recently used one
+        render(&pool, &Renderers::default(), renders[0], u64::MAX).await?;
+        render(&pool, &Renderers::default(), renders[2], 200).await?;
+        let cached = query!(r#"select document as "document!: Uuid" from Cache"#)
+            .map(|x| x.document)
+            .fetch_all(&pool)
+            .await?;
+        assert!(
+            cached.contains(&renders[0]) && !cached.contains(&renders[1]) && cached.contains(&renders[2]),
+            "the least recently used render wasn't evicted, cached are {:?}",
+            cached
+        );
+        assert!(cache::get_usage(&pool, 200).await?.size <= 200);
+
+        let mut sorted = vec![];
+        for (title, tags, size) in [
+            ("b", vec!["sorted"], 30),
+            ("a", vec!["sorted", "extra"], 10),
+            ("a", vec!["sorted"], 20),
+        ] {
+            sorted.push(
+                create(
+                    &pool,
+                    title.to_string(),
+                    Some(DocType::Plain),
+                    tags.into_iter().map(|t| t.to_string()).collect(),
+                    None,
+                    File::Blob(vec![b'x'; size]),
+                )
+                .await?,
+            );
+        }
+        for (id, (author, keywords)) in sorted.iter().zip([
+            ("Zed", r#"["x"]"#),
+            ("adam", r#"["b", "z"]"#),
+            ("Bob", r#"["b", "a"]"#),
+        ]) {
+            query!(
+                "insert into PdfInfo (document, author, keywords) values (?, ?, ?)",
+                id,
+                author,
+                keywords
+            )
+            .execute(&pool)
+            .await?;
+        }
+        let [a, b, c] = [sorted[0], sorted[1], sorted[2]];
+        for (sort, page, page_length, expected) in [
+            (
+                vec![
+                    (SearchSortCriterium::TagCount, false),
+                    (SearchSortCriterium::Title, true),
+                ],
+                0,
+                10,
+                vec![b, c, a],
+            ),
+            (
+                vec![(SearchSortCriterium::MetadataField("author".to_string()), true)],
+                0,
+                10,
+                vec![b, c, a],
+            ),
+            (
+                vec![(SearchSortCriterium::MetadataField("keywords".to_string()), true)],
+                0,
+                10,
+                vec![c, b, a],
+            ),
+            (vec![(SearchSortCriterium::FileSize, true)], 0, 10, vec![b, c, a]),
+            (
+                vec![
+                    (SearchSortCriterium::Title, true),
+                    (SearchSortCriterium::FileSize, false),
+                ],
+                0,
+                10,
+                vec![c, b, a],
+            ),
+            // only the documents titled "a" are stat'ed for the second one
+            (
+                vec![
+                    (SearchSortCriterium::Title, true),
+                    (SearchSortCriterium::FileSize, false),
+                ],
+                1,
+                1,
+                vec![b],
+            ),
+            (
+                vec![
+                    (SearchSortCriterium::Title, false),
+                    (SearchSortCriterium::FileSize, true),
+                ],
+                2,
+                1,
+                vec![c],
+            ),
+        ] {
+            let res = search(
+                &pool,
+                vec!["sorted".to_string()],
+                vec![],
+                String::new(),
+                page,
+                page_length,
+                sort.clone(),
+            )
+            .await?;
+            assert_eq!(
+                res.results.iter().map(|m| m.id).collect::<Vec<_>>(),
+                expected,
+                "sorting by {:?} doesn't match",
+                sort
+            );
+        }
+        assert!(
+            search(
+                &pool,
+                vec![],
+                vec![],
+                String::new(),
+                0,
+                10,
+                vec![(SearchSortCriterium::MetadataField("creator".to_string()), true)],
+            )
+            .await
+            .is_err(),
+            "unknown metadata field wasn't rejected"
+        );
+
+        Ok::<(), eyre::Report>(())
+    }
+    .await
+    {
+        panic!("Error: {}", e);
+    }
+    drop(guard);
+}
+
+#[test]
+async fn thumbnail_text() {
+    assert_eq!(
+        thumbnail::strip_html(
+            "<html><head><title>T</title><style>p {}</style></head><body><h1>Title</h1>\
+            <p>a &lt;b&gt; &amp;amp; c</p><script>let x = '<p>';</script><p>ü&nbsp;end</p></body></html>"
+        ),
+        "\nTitle\n\na <b> &amp; c\n\nü end\n\n"
+    );
+
+    let text = format!(
+        "\n\n{}\n<&>\n{}",
+        "x".repeat(40),
+        (0..20)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    let svg = thumbnail::text_to_svg(&text);
+    assert!(
+        svg.contains(&format!(">{}</text>", "x".repeat(36))),
+        "long line wasn't truncated"
+    );
+    assert!(svg.contains(">&lt;&amp;&gt;</text>"), "text wasn't escaped");
+    assert_eq!(svg.matches("<text").count(), 16, "lines weren't truncated");
+    assert!(
+        svg.contains(">line 13</text>") && !svg.contains(">line 14</text>"),
+        "leading empty lines weren't skipped"
+    );
+}
+
+/// a PDF with two pages, an outline with a nested entry without a page
+/// and `info` as the entries of its Info dictionary
+fn pdf(info: &str) -> Vec<u8> {
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R /Outlines 5 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] >>".to_string(),
+        "<< /Type /Outlines /First 6 0 R /Last 7 0 R /Count 2 >>".to_string(),
+        "<< /Title (Intro) /Parent 5 0 R /Next 7 0 R /Dest [3 0 R /Fit] /First 8 0 R /Last 8 0 R /Count 1 >>".to_string(),
+        "<< /Title (Proofs) /Parent 5 0 R /Prev 6 0 R /Dest [4 0 R /Fit] >>".to_string(),
+        "<< /Title (Motivation) /Parent 6 0 R /Dest /named >>".to_string(),
+        format!("<< {} >>", info),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            objects.len(),
+            xref
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+/// run with `cargo test --release search_benchmark -- --ignored --nocapture`
+#[test]
+#[ignore]
+async fn search_benchmark() {
+    let dtmp = tempdir().unwrap();
+
+    if let Err(e) = async {
+        let pool =
+            crate::db::open(&format!("{}/filespider.sqlite", dtmp.path().display())).await?;
+
+        let mut tx = pool.begin().await?;
+        for i in 0..50_000 {
+            let id = Uuid::new_v4();
+            let title = format!("Document {}", i);
+            let now = chrono::Utc::now();
+            query!(
+                "insert into Document (id, title, type, added, file_extension, accessed) values (?, ?, 'Plain', ?, 'txt', ?)",
+                id,
+                title,
+                now,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            for tag in [format!("tag{}", i % 100), format!("group{}", i % 7)] {
+                query!("insert into Tag (document, tag) values (?, ?)", id, tag)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        for (name, pos_filter, sort) in [
+            ("all by title", vec![], vec![(SearchSortCriterium::Title, true)]),
+            (
+                "one tag by creation time",
+                vec!["tag42".to_string()],
+                vec![(SearchSortCriterium::CreationTime, false)],
+            ),
+            (
+                "all by tag count and title",
+                vec![],
+                vec![
+                    (SearchSortCriterium::TagCount, false),
+                    (SearchSortCriterium::Title, true),
+                ],
+            ),
+        ] {
+            let start = std::time::Instant::now();
+            for page in 0..10 {
+                let res = search(
+                    &pool,
+                    pos_filter.clone(),
+                    vec![],
+                    String::new(),
+                    page,
+                    100,
+                    sort.clone(),
+                )
+                .await?;
+                assert_eq!(res.results.len(), 100, "page {} isn't full", page);
+            }
+            println!("{}: {:?} per page", name, start.elapsed() / 10);
+        }
+
         Ok::<(), eyre::Report>(())
     }
     .await
     {
         panic!("Error: {}", e);
     }
-    drop(dtmp);
 }
@@ -0,0 +1,188 @@
+use std::str::FromStr;
+
+use eyre::Result;
+use sqlx::{query, SqlitePool};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::types::{RenderType, ThumbnailType};
+
+use super::get_thumbnail_file;
+use super::render::{self, execute_command, Renderers};
+
+/// width of PNG thumbnails in pixels
+const THUMBNAIL_WIDTH: u32 = 256;
+const TEXT_LINES: usize = 16;
+const TEXT_COLUMNS: usize = 36;
+
+/// renders the document if needed and returns the path of a small preview image,
+/// the thumbnail is invalidated together with the render it is generated from
+pub async fn thumbnail(
+    pool: &SqlitePool,
+    renderers: &Renderers,
+    id: Uuid,
+    cache_size_limit: u64,
+) -> Result<(String, ThumbnailType)> {
+    let (render_path, render_type) = render::render(pool, renderers, id, cache_size_limit).await?;
+
+    if let Some(thumbnail_type) = query!("select thumbnail from Cache where document = ?", id)
+        .map(|r| r.thumbnail)
+        .fetch_one(pool)
+        .await?
+    {
+        if tokio::fs::try_exists(get_thumbnail_file(id)?).await? {
+            return Ok((
+                get_thumbnail_file(id)?,
+                ThumbnailType::from_str(&thumbnail_type)?,
+            ));
+        }
+    }
+
+    let thumbnail_type = match render_type {
+        RenderType::Pdf => {
+            let temp_dir = tempfile::tempdir()?;
+            let width = THUMBNAIL_WIDTH.to_string();
+
+            execute_command(
+                "pdftoppm",
+                vec![
+                    "-png",
+                    "-f",
+                    "1",
+                    "-l",
+                    "1",
+                    "-scale-to-x",
+                    &width,
+                    "-scale-to-y",
+                    "-1",
+                    "-singlefile",
+                    &render_path,
+                    "thumb",
+                ],
+                Some(temp_dir.path()),
+            )
+            .await?;
+
+            tokio::fs::copy(temp_dir.path().join("thumb.png"), get_thumbnail_file(id)?).await?;
+            ThumbnailType::Png
+        }
+        RenderType::Html => {
+            let html = read_start(&render_path, 64 * 1024).await?;
+            tokio::fs::write(get_thumbnail_file(id)?, text_to_svg(&strip_html(&html))).await?;
+            ThumbnailType::Svg
+        }
+        RenderType::Plain => {
+            let text = read_start(&render_path, 4 * 1024).await?;
+            tokio::fs::write(get_thumbnail_file(id)?, text_to_svg(&text)).await?;
+            ThumbnailType::Svg
+        }
+    };
+
+    let thumbnail_str = thumbnail_type.to_string();
+    query!(
+        "update Cache set thumbnail = ? where document = ?",
+        thumbnail_str,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok((get_thumbnail_file(id)?, thumbnail_type))
+}
+
+/// reads at most `limit` bytes, the file may not be valid UTF-8
+async fn read_start(path: &str, limit: u64) -> Result<String> {
+    let mut buf = vec![];
+    tokio::fs::File::open(path)
+        .await?
+        .take(limit)
+        .read_to_end(&mut buf)
+        .await?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+/// good enough for the pandoc output, drops the head, scripts, styles and all tags
+pub(super) fn strip_html(html: &str) -> String {
+    let body = match html.find("<body") {
+        Some(i) => &html[i..],
+        None => html,
+    };
+
+    let mut text = String::new();
+    let mut in_tag = false;
+    let mut skip_until: Option<&str> = None;
+
+    let mut i = 0;
+    while i < body.len() {
+        let rest = &body[i..];
+        if let Some(end) = skip_until {
+            if rest.starts_with(end) {
+                skip_until = None;
+                i += end.len();
+            } else {
+                i += rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+            }
+            continue;
+        }
+        if rest.starts_with("<script") {
+            skip_until = Some("</script>");
+        } else if rest.starts_with("<style") {
+            skip_until = Some("</style>");
+        }
+
+        let c = rest.chars().next().unwrap();
+        match c {
+            '<' => {
+                in_tag = true;
+                // block level tags end a line
+                if [
+                    "<p", "</p", "<br", "<h", "</h", "<li", "<div", "</div", "<tr",
+                ]
+                .iter()
+                .any(|t| rest.starts_with(t))
+                {
+                    text.push('\n');
+                }
+            }
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+pub(super) fn text_to_svg(text: &str) -> String {
+    let lines = text
+        .lines()
+        .map(|l| l.trim_end())
+        .skip_while(|l| l.trim().is_empty())
+        .take(TEXT_LINES)
+        .enumerate()
+        .map(|(i, l)| {
+            let line: String = l.chars().take(TEXT_COLUMNS).collect();
+            format!(
+                "<text x=\"8\" y=\"{}\" xml:space=\"preserve\">{}</text>",
+                20 + i * 15,
+                line.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("");
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\"><rect width=\"100%\" height=\"100%\" fill=\"white\"/><g font-family=\"monospace\" font-size=\"11\" fill=\"black\">{}</g></svg>",
+        lines,
+        w = THUMBNAIL_WIDTH,
+        h = THUMBNAIL_WIDTH * 4 / 3,
+    )
+}
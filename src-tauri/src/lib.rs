@@ -1,17 +1,17 @@
 #![feature(try_blocks)]
 
 use std::collections::HashMap;
-#[cfg(target_os = "linux")]
 use std::sync::Arc;
 
 use sqlx::SqlitePool;
 use tokio::process;
 use tokio::sync::Mutex;
-use tokio::task::JoinHandle;
 use uuid::Uuid;
 
 use crate::settings::Settings;
+use crate::types::InvalidConfig;
 
+pub mod api;
 pub mod db;
 pub mod directories;
 pub mod document;
@@ -19,29 +19,54 @@ pub mod types;
 
 pub mod settings;
 
+/// clones share the same state, so it can be handed to both tauri and the API server
+#[derive(Clone)]
 pub struct FilespiderState {
-    pool: Mutex<SqlitePool>,
-    editors: Mutex<HashMap<Uuid, process::Child>>,
-    #[allow(clippy::type_complexity)]
-    renderers: Mutex<HashMap<(Uuid, document::render::Hash), Mutex<JoinHandle<()>>>>,
-    settings: Mutex<Settings>,
+    /// the pool is shared by itself, the DB runs in WAL mode so reads don't wait for writes
+    pool: SqlitePool,
+    editors: Arc<Mutex<HashMap<Uuid, process::Child>>>,
+    renderers: Arc<document::render::Renderers>,
+    settings: Arc<Mutex<Settings>>,
+    /// the config that was replaced by the defaults at the start, if it was invalid
+    invalid_config: Option<InvalidConfig>,
     #[cfg(target_os = "linux")]
-    dbus: Mutex<Option<Arc<dbus::nonblock::SyncConnection>>>,
+    dbus: Arc<Mutex<Option<Arc<dbus::nonblock::SyncConnection>>>>,
 }
 
 impl FilespiderState {
     pub fn new(
         pool: SqlitePool,
         settings: Settings,
+        invalid_config: Option<InvalidConfig>,
         #[cfg(target_os = "linux")] dbus: Option<Arc<dbus::nonblock::SyncConnection>>,
     ) -> Self {
         Self {
-            pool: Mutex::new(pool),
-            editors: Mutex::new(HashMap::new()),
-            renderers: Mutex::new(HashMap::new()),
-            settings: Mutex::new(settings),
+            pool,
+            editors: Arc::new(Mutex::new(HashMap::new())),
+            renderers: Arc::default(),
+            settings: Arc::new(Mutex::new(settings)),
+            invalid_config,
             #[cfg(target_os = "linux")]
-            dbus: Mutex::new(dbus),
+            dbus: Arc::new(Mutex::new(dbus)),
         }
     }
 }
+
+/// the data directory of the tests that use the global storage, which can only be initialized once per process.
+/// they hold the guard while they run, so they don't see each other's documents
+#[cfg(test)]
+pub(crate) async fn test_data_directory() -> (
+    tokio::sync::MutexGuard<'static, ()>,
+    &'static std::path::Path,
+) {
+    static DIRECTORY: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+    static LOCK: Mutex<()> = Mutex::const_new(());
+
+    let guard = LOCK.lock().await;
+    let directory = DIRECTORY.get_or_init(|| {
+        let directory = tempfile::tempdir().unwrap();
+        std::env::set_var("FILESPIDER_DATA_PATH", directory.path());
+        directory
+    });
+    (guard, directory.path())
+}
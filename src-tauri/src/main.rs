@@ -1,22 +1,85 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use clap::Parser;
 use eyre::Result;
-use log::error;
+use log::{error, info};
 
+use filespider::api::Listen;
 use filespider::settings::Settings;
 use filespider::*;
 
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// run without a window and only serve the API, e.g. on a home server
+    #[arg(long)]
+    headless: bool,
+
+    /// address the API is served on in headless mode, defaults to localhost and the port from the settings
+    #[arg(long, requires = "headless")]
+    listen: Option<SocketAddr>,
+
+    /// serve the API on this Unix socket instead of TCP in headless mode
+    #[cfg(unix)]
+    #[arg(long, requires = "headless", conflicts_with = "listen")]
+    socket: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
+
     env_logger::Builder::new()
         .filter_level(log::LevelFilter::Trace)
         .init();
 
     directories::create_directories().await?;
+    let _lock = directories::lock_filespider_directory(&directories::get_filespider_directory()?)?;
 
     let pool = db::init().await?;
     sqlx::migrate!().run(&pool).await?;
+
+    let (settings, invalid_config) = Settings::load().await?;
+    document::cache::prune(&pool, settings.cache_size_limit).await?;
+    document::storage::init(&settings.storage)?;
+    document::storage::get()?.collect_garbage().await?;
+    settings::apply(&pool, &settings).await?;
+    let api_settings = settings.api.clone();
+
+    if args.headless {
+        let listen = match args.listen {
+            Some(addr) => Listen::Tcp(addr),
+            None => Listen::localhost(api_settings.port),
+        };
+        #[cfg(unix)]
+        let listen = match args.socket {
+            Some(path) => Listen::Unix(path),
+            None => listen,
+        };
+
+        let state = FilespiderState::new(
+            pool,
+            settings,
+            invalid_config,
+            #[cfg(target_os = "linux")]
+            None,
+        );
+        tokio::spawn(settings::watch(state.clone()));
+
+        return tokio::select! {
+            res = api::serve(state, listen, api_settings.token) => res,
+            _ = tokio::signal::ctrl_c() => {
+                info!("shutting down");
+                Ok(())
+            }
+        };
+    }
+
     #[cfg(target_os = "linux")]
     let (resource, conn) = match dbus_tokio::connection::new_session_sync() {
         Ok((r, c)) => (Some(r), Some(c)),
@@ -33,15 +96,32 @@ async fn main() -> Result<()> {
         });
     }
 
-    let settings = Settings::load().await?;
+    let state = FilespiderState::new(
+        pool,
+        settings,
+        invalid_config,
+        #[cfg(target_os = "linux")]
+        conn,
+    );
+    tokio::spawn(settings::watch(state.clone()));
+
+    if api_settings.enabled {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(
+                state,
+                Listen::localhost(api_settings.port),
+                api_settings.token,
+            )
+            .await
+            {
+                error!("API server stopped: {:?}", e);
+            }
+        });
+    }
 
     tauri::Builder::default()
-        .manage(FilespiderState::new(
-            pool,
-            settings,
-            #[cfg(target_os = "linux")]
-            conn,
-        ))
+        .manage(state)
         .plugin(document::commands::plugin())
         .plugin(settings::commands::plugin())
         .run(tauri::generate_context!())
@@ -1,16 +1,117 @@
 use tauri::plugin::{Builder as PluginBuilder, TauriPlugin};
 use tauri::{Runtime, State};
+use uuid::Uuid;
 
-use crate::settings::DocumentPreset;
+use crate::document;
+use crate::settings::{DocumentPreset, Settings};
+use crate::types::{DocType, InvalidConfig};
 use crate::FilespiderState;
 
+#[tauri::command]
+pub async fn get_settings(state: State<'_, FilespiderState>) -> Result<Settings, String> {
+    Ok(state.settings.lock().await.clone())
+}
+
+/// replaces all settings, they are validated first and take effect without a restart
+#[tauri::command]
+pub async fn update_settings(
+    state: State<'_, FilespiderState>,
+    settings: Settings,
+) -> Result<(), String> {
+    let mut current = state.settings.lock().await;
+    crate::settings::replace(&state.pool, &mut current, settings)
+        .await
+        .map_err(|x| format!("{x:?}"))?;
+    current.save().await.map_err(|x| format!("{x:?}"))
+}
+
+/// set if the config was invalid at the start and the defaults are used instead
+#[tauri::command]
+pub async fn get_invalid_config(
+    state: State<'_, FilespiderState>,
+) -> Result<Option<InvalidConfig>, String> {
+    Ok(state.invalid_config.clone())
+}
+
 #[tauri::command]
 pub async fn get_presets(state: State<'_, FilespiderState>) -> Result<Vec<DocumentPreset>, String> {
     Ok(state.settings.lock().await.presets.clone())
 }
 
+/// saves the files, tags and type of the document as a new preset
+#[tauri::command]
+pub async fn create_preset_from_document(
+    state: State<'_, FilespiderState>,
+    id: Uuid,
+    name: String,
+) -> Result<(), String> {
+    let preset = document::template::preset_from_document(&state.pool, id, name)
+        .await
+        .map_err(|x| format!("{x:?}"))?;
+
+    let mut settings = state.settings.lock().await;
+    if let Err(e) = settings.add_preset(preset.clone()) {
+        // e.g. the name is taken, the copied files would never be used
+        document::template::remove_files(&preset, &settings.presets)
+            .await
+            .map_err(|x| format!("{x:?}"))?;
+        return Err(format!("{e:?}"));
+    }
+    settings.save().await.map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn update_preset(
+    state: State<'_, FilespiderState>,
+    name: String,
+    new_name: String,
+    tags: Vec<String>,
+    extension: Option<String>,
+    doc_type: Option<DocType>,
+) -> Result<(), String> {
+    let mut settings = state.settings.lock().await;
+    settings
+        .update_preset(&name, new_name, tags, extension, doc_type)
+        .map_err(|x| format!("{x:?}"))?;
+    settings.save().await.map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn delete_preset(state: State<'_, FilespiderState>, name: String) -> Result<(), String> {
+    let mut settings = state.settings.lock().await;
+    let preset = settings
+        .delete_preset(&name)
+        .map_err(|x| format!("{x:?}"))?;
+    settings.save().await.map_err(|x| format!("{x:?}"))?;
+
+    document::template::remove_files(&preset, &settings.presets)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
+#[tauri::command]
+pub async fn reorder_presets(
+    state: State<'_, FilespiderState>,
+    names: Vec<String>,
+) -> Result<(), String> {
+    let mut settings = state.settings.lock().await;
+    settings
+        .reorder_presets(names)
+        .map_err(|x| format!("{x:?}"))?;
+    settings.save().await.map_err(|x| format!("{x:?}"))
+}
+
 pub fn plugin<R: Runtime>() -> TauriPlugin<R> {
     PluginBuilder::new("settings")
-        .invoke_handler(tauri::generate_handler![get_presets])
+        .invoke_handler(tauri::generate_handler![
+            get_settings,
+            update_settings,
+            get_invalid_config,
+            get_presets,
+            create_preset_from_document,
+            update_preset,
+            delete_preset,
+            reorder_presets
+        ])
         .build()
 }
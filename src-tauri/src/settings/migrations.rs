@@ -0,0 +1,47 @@
+use eyre::{eyre, Result, WrapErr};
+use serde_json::{Map, Value};
+
+/// the version of the format [`super::Settings`] describes, bump it together with a new step in [`MIGRATIONS`]
+pub const CURRENT_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// step `i` turns a config of version `i` into version `i + 1`
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [unversioned];
+
+/// brings a parsed config to the current version, returns the version it had
+pub fn migrate(config: &mut Value) -> Result<u32> {
+    let config = config
+        .as_object_mut()
+        .ok_or(eyre!("the settings aren't an object"))?;
+
+    let version = match config.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .ok_or(eyre!("the settings version {} isn't a number", v))? as u32,
+    };
+    if version > CURRENT_VERSION {
+        return Err(eyre!(
+            "the settings are from a newer version of FileSpider, format {} is newer than {}",
+            version,
+            CURRENT_VERSION
+        ));
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(config).wrap_err(format!(
+            "failed to migrate the settings from version {}",
+            from
+        ))?;
+    }
+    config.insert("version".to_string(), CURRENT_VERSION.into());
+
+    Ok(version)
+}
+
+/// configs from before the format was versioned, `file_watcher` was required but missing in older ones
+fn unversioned(config: &mut Map<String, Value>) -> Result<()> {
+    config.entry("file_watcher").or_insert(Value::Bool(false));
+    Ok(())
+}
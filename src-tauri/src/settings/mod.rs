@@ -1,18 +1,124 @@
-use eyre::{eyre, Result};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use eyre::{eyre, Result, WrapErr};
+use log::{error, info};
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
 
 use crate::directories::get_filespider_directory;
-use crate::document::File;
 use crate::document::File::Blob;
-use crate::types::DocType;
+use crate::document::{auto_tag, tags, template, File};
+use crate::types::{DocType, InvalidConfig, StorageBackend};
+use crate::FilespiderState;
 
 pub mod commands;
+mod migrations;
+
+/// how often `config.json5` is checked for external edits
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
 
-#[derive(Serialize, Deserialize)]
+/// new fields need a serde default or a step in `migrations`, otherwise older configs can't be read
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Settings {
+    /// the format of the config, it is migrated when it is loaded
+    pub version: u32,
     pub text_editor: (String, Vec<String>),
+    #[serde(default = "default_presets")]
     pub presets: Vec<DocumentPreset>,
+    #[serde(default)]
     pub file_watcher: bool,
+    /// in bytes
+    #[serde(default = "default_cache_size_limit")]
+    pub cache_size_limit: u64,
+    #[serde(default)]
+    pub api: ApiSettings,
+    /// only changed by `transfer_storage`
+    #[serde(default)]
+    pub storage: StorageBackend,
+    #[serde(default)]
+    pub tags: TagSettings,
+    /// applied in order when documents are created, imported or edited
+    #[serde(default)]
+    pub tag_rules: Vec<TagRule>,
+}
+
+/// how tags are normalized before they are stored or searched for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TagSettings {
+    /// "Math" and "math" are the same tag
+    pub case_folding: bool,
+    /// composed and decomposed forms of characters like "ä" are the same tag
+    pub unicode_nfc: bool,
+}
+
+impl Default for TagSettings {
+    fn default() -> Self {
+        Self {
+            case_folding: true,
+            unicode_nfc: true,
+        }
+    }
+}
+
+/// assigns `tags` to every document that matches all conditions that are set, patterns are regexes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TagRule {
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub doc_type: Option<DocType>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// matched against the main file, gzipped files like `.xopp` are unpacked first
+    #[serde(default)]
+    pub content: Option<String>,
+    /// only matches while the document is created from a file in this folder, not when the rules are re-applied
+    #[serde(default)]
+    pub source_folder: Option<String>,
+    /// patterns for the metadata of imported PDFs by field, one of `author`, `subject` and `keywords`
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// the HTTP API is only bound to localhost, every request needs `Authorization: Bearer <token>`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    pub token: String,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 7318,
+            token: String::new(),
+        }
+    }
+}
+
+fn default_cache_size_limit() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_presets() -> Vec<DocumentPreset> {
+    vec![
+        DocumentPreset::from_strs(
+            "LaTeX",
+            vec![],
+            Some("tex"),
+            Some(DocType::LaTeX),
+            Some(include_bytes!("../../assets/latex_template.tex")),
+        ),
+        DocumentPreset::from_strs(
+            "XOPP",
+            vec![],
+            Some("xopp"),
+            Some(DocType::XournalPP),
+            Some(include_bytes!("../../assets/xopp_template.xopp")),
+        ),
+    ]
 }
 
 impl Settings {
@@ -33,61 +139,293 @@ impl Settings {
         };
 
         Ok(Self {
+            version: migrations::CURRENT_VERSION,
             text_editor: (
                 editor.0.to_string(),
                 editor.1.into_iter().map(|s| s.to_string()).collect(),
             ),
-            presets: vec![
-                DocumentPreset::from_strs(
-                    "LaTeX",
-                    vec![],
-                    Some("tex"),
-                    Some(DocType::LaTeX),
-                    Some(include_bytes!("../../assets/latex_template.tex")),
-                ),
-                DocumentPreset::from_strs(
-                    "XOPP",
-                    vec![],
-                    Some("xopp"),
-                    Some(DocType::XournalPP),
-                    Some(include_bytes!("../../assets/xopp_template.xopp")),
-                ),
-            ],
+            presets: default_presets(),
             file_watcher: false,
+            cache_size_limit: default_cache_size_limit(),
+            api: ApiSettings {
+                token: generate_token(),
+                ..Default::default()
+            },
+            storage: StorageBackend::default(),
+            tags: TagSettings::default(),
+            tag_rules: vec![],
         })
     }
 
+    /// checks what the types can't, all problems are reported at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+
+        if self.version != migrations::CURRENT_VERSION {
+            problems.push(format!(
+                "the settings version is {} instead of {}",
+                self.version,
+                migrations::CURRENT_VERSION
+            ));
+        }
+
+        if self.text_editor.0.trim().is_empty() {
+            problems.push("the text editor is empty".to_string());
+        }
+
+        for (i, preset) in self.presets.iter().enumerate() {
+            if self.presets[..i].iter().any(|p| p.name == preset.name) {
+                problems.push(format!("there are several presets named {}", preset.name));
+            }
+            // attachments are written into the document, a path could point anywhere
+            for (name, _) in preset.attachments.iter() {
+                if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                    problems.push(format!(
+                        "preset {} has an attachment with the invalid name {}",
+                        preset.name, name
+                    ));
+                }
+            }
+            for variable in preset.variables.iter() {
+                if template::BUILTIN_VARIABLES.contains(&variable.as_str()) {
+                    problems.push(format!(
+                        "preset {} declares the builtin variable {}",
+                        preset.name, variable
+                    ));
+                }
+            }
+        }
+
+        if self.api.enabled && self.api.port == 0 {
+            problems.push("the API is enabled without a port".to_string());
+        }
+
+        if let Err(e) = auto_tag::validate(&self.tag_rules) {
+            problems.push(format!("{:#}", e));
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(eyre!("invalid settings:\n{}", problems.join("\n"))),
+        }
+    }
+
+    /// preset names are unique
+    pub fn add_preset(&mut self, preset: DocumentPreset) -> Result<()> {
+        if self.presets.iter().any(|p| p.name == preset.name) {
+            return Err(eyre!("there already is a preset named {}", preset.name));
+        }
+        self.presets.push(preset);
+        Ok(())
+    }
+
+    /// changes everything but the files and the variables of the preset
+    pub fn update_preset(
+        &mut self,
+        name: &str,
+        new_name: String,
+        tags: Vec<String>,
+        extension: Option<String>,
+        doc_type: Option<DocType>,
+    ) -> Result<()> {
+        if new_name != name && self.presets.iter().any(|p| p.name == new_name) {
+            return Err(eyre!("there already is a preset named {}", new_name));
+        }
+
+        let preset = self.get_preset_mut(name)?;
+        preset.name = new_name;
+        preset.tags = tags;
+        preset.extension = extension;
+        preset.doc_type = doc_type;
+        Ok(())
+    }
+
+    /// returns the deleted preset
+    pub fn delete_preset(&mut self, name: &str) -> Result<DocumentPreset> {
+        let position = self
+            .presets
+            .iter()
+            .position(|p| p.name == name)
+            .ok_or(eyre!("no preset named {}", name))?;
+        Ok(self.presets.remove(position))
+    }
+
+    /// `names` has to contain exactly the names of the existing presets
+    pub fn reorder_presets(&mut self, names: Vec<String>) -> Result<()> {
+        let mut current: Vec<_> = self.presets.iter().map(|p| p.name.clone()).collect();
+        let mut new = names.clone();
+        current.sort();
+        new.sort();
+        if current != new {
+            return Err(eyre!(
+                "the new order doesn't contain the names of the presets"
+            ));
+        }
+
+        self.presets
+            .sort_by_key(|p| names.iter().position(|n| *n == p.name));
+        Ok(())
+    }
+
+    fn get_preset_mut(&mut self, name: &str) -> Result<&mut DocumentPreset> {
+        self.presets
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or(eyre!("no preset named {}", name))
+    }
+
     pub async fn save(&self) -> Result<()> {
         save_config(self).await?;
         Ok(())
     }
 
-    pub async fn load() -> Result<Settings> {
+    /// the defaults are used and saved if there is no config, or if it is invalid after it was backed up
+    pub async fn load() -> Result<(Settings, Option<InvalidConfig>)> {
         if tokio::fs::try_exists(get_config_file()?).await? {
-            let settings: Result<Settings> = try {
-                let settings_str = tokio::fs::read_to_string(get_config_file()?).await?;
-                let s: Settings = json5::from_str(&settings_str)?;
-                s
-            };
-            if let Ok(s) = settings {
-                return Ok(s);
+            match read_config().await {
+                Ok((mut s, version)) => {
+                    let mut changed = false;
+                    if version != migrations::CURRENT_VERSION {
+                        let backup = backup_config().await?;
+                        info!(
+                            "migrated settings from version {}, the old ones are in {}",
+                            version, backup
+                        );
+                        changed = true;
+                    }
+                    if s.api.token.is_empty() {
+                        s.api.token = generate_token();
+                        changed = true;
+                    }
+                    if changed {
+                        save_config(&s).await?;
+                    }
+                    return Ok((s, None));
+                }
+                Err(e) => {
+                    let backup = backup_config().await?;
+                    error!(
+                        "the settings are invalid, using the defaults, the old ones are in {}: {:?}",
+                        backup, e
+                    );
+
+                    let mut settings = Settings::default().await?;
+                    // the documents can only be read with the backend they were stored with
+                    settings.storage = read_storage_backend(&get_filespider_directory()?)
+                        .await
+                        .unwrap_or_default();
+                    save_config(&settings).await?;
+
+                    return Ok((
+                        settings,
+                        Some(InvalidConfig {
+                            backup,
+                            error: format!("{:?}", e),
+                        }),
+                    ));
+                }
             }
         }
         let settings = Settings::default().await?;
 
         save_config(&settings).await?;
 
-        Ok(settings)
+        Ok((settings, None))
+    }
+}
+
+/// the settings in the current format and the version they were stored in
+async fn read_config() -> Result<(Settings, u32)> {
+    let mut config: serde_json::Value =
+        json5::from_str(&tokio::fs::read_to_string(get_config_file()?).await?)
+            .wrap_err("failed to parse config.json5")?;
+    let version = migrations::migrate(&mut config)?;
+
+    let settings: Settings =
+        serde_json::from_value(config).wrap_err("config.json5 doesn't match the settings")?;
+    settings.validate()?;
+
+    Ok((settings, version))
+}
+
+/// copies `config.json5` next to itself before it is replaced, returns the path of the copy
+async fn backup_config() -> Result<String> {
+    let backup = format!(
+        "{}.{}.bak",
+        get_config_file()?,
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    tokio::fs::copy(get_config_file()?, &backup).await?;
+    Ok(backup)
+}
+
+/// hands the settings to the modules that keep their own copy, API changes only take effect after a restart
+pub async fn apply(pool: &SqlitePool, settings: &Settings) -> Result<()> {
+    tags::configure(&settings.tags);
+    tags::normalize_existing(pool).await?;
+    auto_tag::configure(&settings.tag_rules)
+}
+
+/// validates `new` and makes it the current settings, they aren't saved
+pub async fn replace(pool: &SqlitePool, current: &mut Settings, new: Settings) -> Result<()> {
+    new.validate()?;
+    if new.storage != current.storage {
+        return Err(eyre!(
+            "the storage backend can only be changed with transfer_storage while FileSpider isn't running"
+        ));
+    }
+
+    apply(pool, &new).await?;
+    *current = new;
+
+    Ok(())
+}
+
+/// reloads `config.json5` when it is edited, invalid edits are logged and ignored until they are fixed
+pub async fn watch(state: FilespiderState) {
+    let modified = || async {
+        let metadata = tokio::fs::metadata(get_config_file()?).await?;
+        Ok::<SystemTime, eyre::Report>(metadata.modified()?)
+    };
+
+    let mut last = modified().await.ok();
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let current = modified().await.ok();
+        if current == last {
+            continue;
+        }
+        last = current;
+
+        let reloaded: Result<()> = try {
+            let (new, _) = read_config().await?;
+            replace(&state.pool, &mut *state.settings.lock().await, new).await?;
+        };
+        match reloaded {
+            Ok(()) => info!("reloaded settings"),
+            Err(e) => error!("Failed to reload settings: {:?}", e),
+        }
     }
 }
 
+/// a template for new documents, placeholders like `{{title}}` in its text files are filled in
+/// by [`crate::document::template::create`]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DocumentPreset {
     pub name: String,
     pub tags: Vec<String>,
     pub extension: Option<String>,
     pub doc_type: Option<DocType>,
+    /// the main file
     pub file: File,
+    /// additional files by file name, e.g. a bibliography or images
+    #[serde(default)]
+    pub attachments: Vec<(String, File)>,
+    /// placeholders the user is asked to fill in when the document is created
+    #[serde(default)]
+    pub variables: Vec<String>,
 }
 
 impl DocumentPreset {
@@ -108,10 +446,33 @@ impl DocumentPreset {
             } else {
                 File::None
             },
+            attachments: vec![],
+            variables: vec![],
         }
     }
 }
 
+fn generate_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// reads only the storage backend from the settings of another filespider directory
+pub async fn read_storage_backend(root: &str) -> Result<StorageBackend> {
+    #[derive(Deserialize)]
+    struct StorageSettings {
+        #[serde(default)]
+        storage: StorageBackend,
+    }
+
+    let file = format!("{}/config.json5", root);
+    if !tokio::fs::try_exists(&file).await? {
+        return Ok(StorageBackend::default());
+    }
+
+    let settings: StorageSettings = json5::from_str(&tokio::fs::read_to_string(file).await?)?;
+    Ok(settings.storage)
+}
+
 pub fn get_config_file() -> Result<String> {
     Ok(format!("{}/config.json5", get_filespider_directory()?))
 }
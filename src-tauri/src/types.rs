@@ -8,7 +8,7 @@ use uuid::Uuid;
 
 use crate::settings::Settings;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct Meta {
     pub title: String,
     pub doc_type: DocType,
@@ -19,13 +19,137 @@ pub struct Meta {
     pub extension: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum MetaPatch {
     ChangeTitle(String),
     AddTag(String),
     RemoveTag(String),
 }
 
+/// what happened to a document, see `document::journal`
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct JournalEntry {
+    pub seq: i64,
+    pub time: NaiveDateTime,
+    pub document: Uuid,
+    pub operation: JournalOperation,
+}
+
+/// carries enough to revert the operation, except for the contents of files
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum JournalOperation {
+    Create(Meta),
+    ImportPdf(Meta),
+    Patch {
+        patch: MetaPatch,
+        inverse: MetaPatch,
+    },
+    ReplaceFile,
+    /// the metadata before the deletion
+    Delete(Meta),
+    Access,
+    /// created or overwritten by a sync
+    Sync(Meta),
+    /// a deletion was undone
+    Restore(Meta),
+}
+
+/// metadata read from the Info dictionary and outline of an imported PDF
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct PdfInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    /// suggested tags, not applied automatically
+    pub keywords: Vec<String>,
+    pub outline: Vec<OutlineEntry>,
+}
+
+/// page starts at 0, depth 0 is the top level
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct OutlineEntry {
+    pub title: String,
+    pub depth: u32,
+    pub page: Option<u32>,
+}
+
+/// inconsistencies between the DB and the document directories, see `document::fsck::fsck`
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct FsckReport {
+    /// documents in the storage backend without a row in Document
+    pub orphaned_directories: Vec<Uuid>,
+    /// rows in Document whose files are missing from the storage backend
+    pub missing_directories: Vec<Uuid>,
+    pub dangling_tags: Vec<(Uuid, String)>,
+    pub dangling_cache_entries: Vec<Uuid>,
+    pub dangling_pdf_info: Vec<Uuid>,
+    /// memberships of missing documents or in missing collections, by collection
+    pub dangling_collection_entries: Vec<(String, Uuid)>,
+    /// sequence numbers of journal entries whose operation can't be read
+    pub invalid_journal_entries: Vec<i64>,
+    /// sequence numbers of undo and redo entries that can't be applied, e.g. because their document is missing
+    pub stale_history_entries: Vec<i64>,
+    /// tags without a TagInfo row and TagInfo rows of aliases
+    pub inconsistent_tag_info: Vec<String>,
+    /// documents whose sync state is recorded for this replica itself
+    pub invalid_sync_log_entries: Vec<Uuid>,
+    /// leftovers of document creations that failed midway
+    pub stale_staging_directories: Vec<String>,
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_directories.is_empty()
+            && self.missing_directories.is_empty()
+            && self.dangling_tags.is_empty()
+            && self.dangling_cache_entries.is_empty()
+            && self.dangling_pdf_info.is_empty()
+            && self.dangling_collection_entries.is_empty()
+            && self.invalid_journal_entries.is_empty()
+            && self.stale_history_entries.is_empty()
+            && self.inconsistent_tag_info.is_empty()
+            && self.invalid_sync_log_entries.is_empty()
+            && self.stale_staging_directories.is_empty()
+    }
+}
+
+/// what `document::sync::sync` changed, by document id
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct SyncReport {
+    pub pulled: Vec<Uuid>,
+    pub pushed: Vec<Uuid>,
+    pub removed_locally: Vec<Uuid>,
+    pub removed_remotely: Vec<Uuid>,
+    /// ids of the copies that were created for documents changed on both sides
+    pub conflict_copies: Vec<Uuid>,
+}
+
+/// what `document::auto_tag::apply_all` changed
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct TagRuleReport {
+    /// number of tags that were added
+    pub added: u32,
+    /// documents the rules couldn't be applied to and the error
+    pub failed: Vec<(Uuid, String)>,
+}
+
+/// a config that couldn't be loaded at the start, FileSpider runs with the defaults instead
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InvalidConfig {
+    /// where the invalid config was copied to
+    pub backup: String,
+    pub error: String,
+}
+
+/// sizes are in bytes
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct CacheUsage {
+    pub size: u64,
+    pub limit: u64,
+    pub entries: u32,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub enum RenderType {
     Plain,
@@ -33,6 +157,20 @@ pub enum RenderType {
     Pdf,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum ThumbnailType {
+    Png,
+    Svg,
+}
+
+/// see `document::storage`, existing documents are moved with `document::storage::transfer`
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub enum StorageBackend {
+    #[default]
+    Directory,
+    Blob,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum DocType {
     Plain,
@@ -41,15 +179,102 @@ pub enum DocType {
     LaTeX,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum SearchSortCriterium {
     CreationTime,
     AccessTime,
+    /// case-insensitive, numbers are compared by value
     Title,
+    /// the order of the named collection, documents outside of it don't match
+    CollectionOrder(String),
+    /// the latest change of any file of the document
+    ModificationTime,
+    DocType,
+    TagCount,
+    /// the total size of the files of the document
+    FileSize,
+    /// how well the title matches the crib, descending puts exact matches first
+    Relevance,
+    /// a field of the PDF info: "author", "subject" or "keywords"
+    MetadataField(String),
 }
 
-/// bool is true for ascending, false for descending
-pub type SearchSorting = (SearchSortCriterium, bool);
+/// sort keys by priority, bool is true for ascending, false for descending.
+/// documents that are equal in every key are ordered by their id
+pub type SearchSorting = Vec<(SearchSortCriterium, bool)>;
+
+/// a page of search results and statistics about all documents matching the search
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResponse {
+    pub results: Vec<Meta>,
+    /// number of matching documents on all pages
+    pub total: u32,
+    /// tags of the matching documents and how many of them have each, most frequent first
+    pub tags: Vec<Facet<String>>,
+    pub doc_types: Vec<Facet<DocType>>,
+    /// properties of the tags in `tags`
+    pub tag_info: Vec<TagInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Facet<T> {
+    pub value: T,
+    pub count: u32,
+}
+
+/// a named combination of the arguments of `document::search`
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SavedSearch {
+    pub name: String,
+    pub pos_filter: Vec<String>,
+    pub neg_filter: Vec<String>,
+    pub crib: String,
+    pub sort: SearchSorting,
+    /// only the first `limit` results match, e.g. for "the 10 most recently opened documents"
+    pub limit: Option<u32>,
+}
+
+/// `alias` is replaced by `tag` wherever tags are used
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct TagAlias {
+    pub alias: String,
+    pub tag: String,
+}
+
+/// a tag exists until it is deleted, even if no document has it
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct TagInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub properties: TagProperties,
+    /// number of documents with the tag
+    pub documents: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct TagProperties {
+    /// a CSS color, e.g. `#ff8800`
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub description: Option<String>,
+    /// pinned tags are listed first
+    pub pinned: bool,
+}
+
+/// between 0 and 1, the mean of how often the tag appears with the tags of the document
+/// and how similar the text of the document is to the documents with the tag
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TagSuggestion {
+    pub tag: String,
+    pub score: f64,
+}
+
+/// a manually ordered list of documents, e.g. the lectures of a course
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Collection {
+    pub name: String,
+    pub documents: Vec<Uuid>,
+}
 
 impl FromStr for RenderType {
     type Err = eyre::Report;
@@ -75,6 +300,50 @@ impl Display for RenderType {
     }
 }
 
+impl FromStr for ThumbnailType {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(ThumbnailType::Png),
+            "svg" => Ok(ThumbnailType::Svg),
+            _ => Err(eyre!("unknown thumbnail type {}", s)),
+        }
+    }
+}
+
+impl Display for ThumbnailType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            ThumbnailType::Png => "png",
+            ThumbnailType::Svg => "svg",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "directory" => Ok(StorageBackend::Directory),
+            "blob" => Ok(StorageBackend::Blob),
+            _ => Err(eyre!("unknown storage backend {}", s)),
+        }
+    }
+}
+
+impl Display for StorageBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            StorageBackend::Directory => "directory",
+            StorageBackend::Blob => "blob",
+        };
+        write!(f, "{}", str)
+    }
+}
+
 impl FromStr for DocType {
     type Err = eyre::Report;
 